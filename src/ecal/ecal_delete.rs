@@ -0,0 +1,65 @@
+use fuel_asm::RegId;
+use fuel_vm::prelude::Interpreter;
+
+use super::filter::Filter;
+use super::{EcalError, EcalResult};
+
+// Deletes the rows of a struct matching a filter. Rows of the array tables
+// pointing to them go with them, their foreign keys cascade, and so do the
+// links to them as elements of arrays. Rows the deleted rows point to are
+// kept, other values may share them, and a row that is the field of another
+// row can't be deleted, the statement fails.
+pub fn delete<S, Tx>(vm: &mut Interpreter<S, Tx, super::MyEcal>, rb: RegId) -> EcalResult<()> {
+    let [type_id, filter_addr, filter_len] = super::read_words(vm, rb)?;
+    let type_id = type_id as usize;
+    #[cfg(debug_assertions)]
+    println!("> ECAL::delete(type_id={type_id})");
+
+    let filter = Filter::parse(&super::read_memory(vm, filter_addr, filter_len)?)?;
+    let gas_costs = vm.ecal_state().gas_costs;
+    vm.gas_charge(gas_costs.statements(1))?;
+    let deleted = delete_rows(vm.ecal_state(), type_id, &filter)?;
+    vm.gas_charge(gas_costs.rows(deleted))?;
+
+    // Return the number of deleted rows through the rB register
+    vm.registers_mut()[rb] = deleted;
+    Ok(())
+}
+
+// Returns the number of deleted rows.
+pub(super) fn delete_rows(
+    ecal: &super::MyEcal,
+    type_id: usize,
+    filter: &Filter,
+) -> EcalResult<u64> {
+    let decl = ecal
+        .abi
+        .types
+        .get(&type_id)
+        .ok_or(EcalError::UnknownTypeId(type_id))?;
+    let struct_name =
+        decl.type_field
+            .strip_prefix("struct ")
+            .ok_or_else(|| EcalError::Unsupported {
+                type_id,
+                reason: format!("can only delete structs, not '{}'", decl.type_field),
+            })?;
+    // Buffered saves of the rows must not bring them back.
+    if let Some(buffer) = &ecal.write_buffer {
+        buffer.flush(ecal.db()?)?;
+    }
+
+    let mut args = vec![];
+    let table = format!("\"{struct_name}\"");
+    let condition = filter.condition(ecal, type_id, &table, &mut args)?;
+    let sql = format!("DELETE FROM {table} WHERE {condition}");
+    #[cfg(debug_assertions)]
+    println!(">> DELETE_STMT\n{sql}");
+    ecal.db()?
+        .execute(&sql, &args)
+        .map_err(|source| EcalError::Sql {
+            type_id,
+            sql,
+            source,
+        })
+}
@@ -1,139 +1,809 @@
-use fuel_abi_types::abi::program::TypeDeclaration;
-use fuel_asm::RegId;
-use fuel_vm::{
-    error::SimpleResult,
-    prelude::{Interpreter, MemoryRange},
-};
-use fuels::core::codec::ABIEncoder;
+use fuel_abi_types::abi::program::{TypeApplication, TypeDeclaration};
+use fuel_asm::{PanicReason, RegId};
+use fuel_vm::prelude::Interpreter;
+use fuels::core::codec::{ABIDecoder, ABIEncoder};
 use fuels::types::param_types::ParamType;
-use fuels::types::Token;
+use fuels::types::{enum_variants::EnumVariants, StaticStringToken, Token};
 
-use sqlx::{Pool, Postgres, Row};
+use sqlx::{postgres::PgRow, Row, ValueRef};
+use std::cell::Cell;
 use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 
+use super::filter::{Filter, OrderBy};
+use super::{EcalError, EcalResult, GasCosts};
+use crate::db::SqlArg;
 use crate::extensions::*;
+use crate::sql::sql_table_builder::{
+    array_parent_column, array_table_name, column_option, tuple_element_column, ENUM_VARIANT_COLUMN,
+};
 
-pub fn load<S, Tx>(vm: &mut Interpreter<S, Tx, super::MyEcal>, rb: RegId) -> SimpleResult<()> {
-    let type_id = vm.registers()[rb];
+pub fn load<S, Tx>(vm: &mut Interpreter<S, Tx, super::MyEcal>, rb: RegId) -> EcalResult<()> {
+    let [type_id, filter_addr, filter_len] = super::read_words(vm, rb)?;
+    let type_id = type_id as usize;
     #[cfg(debug_assertions)]
     println!("> ECAL::load(type_id={type_id})");
 
-    let struct_name = vm
-        .ecal_state_mut()
+    let filter = Filter::parse(&super::read_memory(vm, filter_addr, filter_len)?)?;
+    let struct_name = struct_name(vm.ecal_state(), type_id)?;
+    let gas_costs = vm.ecal_state().gas_costs;
+    // Charged before the query runs, as for `save`, for the one row it may
+    // return.
+    vm.gas_charge(gas_costs.statements(1).saturating_add(gas_costs.rows(1)))?;
+    // Read your own writes.
+    if let Some(buffer) = &vm.ecal_state().write_buffer {
+        buffer.flush(vm.ecal_state().db()?)?;
+    }
+    let query = Query {
+        filter,
+        order_by: OrderBy::default(),
+        limit: 1,
+        offset: 0,
+    };
+    let meter = Meter::new(gas_costs, vm.registers()[RegId::CGAS]);
+    let loaded = load_any(vm.ecal_state(), &meter, &struct_name, type_id, &query);
+    // Fails with `OutOfGas` if the load ran out of it.
+    vm.gas_charge(meter.used())?;
+    let (values, _) = loaded?;
+    let struct_token = values.into_iter().next();
+    // An `Option<T>`, `None` if no row matches.
+    let encode_error = |reason: String| EcalError::Encode {
+        type_id: Some(type_id),
+        reason,
+    };
+    let variants = EnumVariants::new(vec![
+        ParamType::Unit,
+        super::param_type(&vm.ecal_state().abi, type_id)?,
+    ])
+    .map_err(|e| encode_error(e.to_string()))?;
+    let option_token = match struct_token {
+        Some(token) => Token::Enum(Box::new((1, token, variants))),
+        None => Token::Enum(Box::new((0, Token::Unit, variants))),
+    };
+    let output_bytes = ABIEncoder::encode(&vec![option_token])
+        .map_err(|e| encode_error(e.to_string()))?
+        .resolve(0);
+    vm.gas_charge(gas_costs.decoding(output_bytes.len() as u64))?;
+
+    // Return the address of the output tuple through the rB register
+    super::write_output(vm, rb, &output_bytes)
+}
+
+// The most values `load_many` returns at once, so the rows charged for up
+// front stay bounded. Scripts page through more with the offset.
+const MAX_PAGE: u64 = 1_000;
+
+// Loads up to `limit` values of the struct `type_id` matching a filter, at
+// most `MAX_PAGE`, with the total number of matches, in newly allocated
+// memory:
+//
+//   total len value[0] ... value[len - 1]
+//
+// The values in their ABI encoding, one after another.
+pub fn load_many<S, Tx>(vm: &mut Interpreter<S, Tx, super::MyEcal>, rb: RegId) -> EcalResult<()> {
+    let [type_id, filter_addr, filter_len, order_addr, order_len, limit, offset] =
+        super::read_words(vm, rb)?;
+    let type_id = type_id as usize;
+    #[cfg(debug_assertions)]
+    println!("> ECAL::load_many(type_id={type_id}, limit={limit}, offset={offset})");
+
+    let filter = Filter::parse(&super::read_memory(vm, filter_addr, filter_len)?)?;
+    let order_by = OrderBy::parse(&super::read_memory(vm, order_addr, order_len)?)?;
+    let struct_name = struct_name(vm.ecal_state(), type_id)?;
+    let limit = limit.min(MAX_PAGE);
+    let gas_costs = vm.ecal_state().gas_costs;
+    // Charged before the queries run, as for `save`, for every row the page
+    // may hold.
+    vm.gas_charge(
+        gas_costs
+            .statements(2)
+            .saturating_add(gas_costs.rows(limit)),
+    )?;
+    // Read your own writes.
+    if let Some(buffer) = &vm.ecal_state().write_buffer {
+        buffer.flush(vm.ecal_state().db()?)?;
+    }
+    let query = Query {
+        filter,
+        order_by,
+        limit,
+        offset,
+    };
+    let meter = Meter::new(gas_costs, vm.registers()[RegId::CGAS]);
+    let loaded = load_any(vm.ecal_state(), &meter, &struct_name, type_id, &query);
+    // Fails with `OutOfGas` if the load ran out of it.
+    vm.gas_charge(meter.used())?;
+    let (tokens, total) = loaded?;
+    // An empty page, e.g. one past the last match, has no row to carry the
+    // total, so it's counted by another statement. Under READ COMMITTED that
+    // sees the rows committed in between, but there are no values to be
+    // inconsistent with.
+    let total = match total {
+        Some(total) => total,
+        None => count(vm.ecal_state(), &struct_name, type_id, &query.filter)?,
+    };
+    let len = tokens.len() as u64;
+    let values = ABIEncoder::encode(&[Token::Array(tokens)])
+        .map_err(|e| EcalError::Encode {
+            type_id: Some(type_id),
+            reason: e.to_string(),
+        })?
+        .resolve(0);
+    vm.gas_charge(gas_costs.decoding(values.len() as u64))?;
+
+    let mut output_bytes = total.to_be_bytes().to_vec();
+    output_bytes.extend(len.to_be_bytes());
+    output_bytes.extend(values);
+    // Return the address of the output through the rB register
+    super::write_output(vm, rb, &output_bytes)
+}
+
+// The table of a loadable type, only structs have one.
+fn struct_name(ecal: &super::MyEcal, type_id: usize) -> EcalResult<String> {
+    let decl = ecal
         .abi
-        .type_declaration(type_id as usize)
-        .type_field
+        .types
+        .get(&type_id)
+        .ok_or(EcalError::UnknownTypeId(type_id))?;
+    decl.type_field
         .strip_prefix("struct ")
-        .unwrap()
-        .to_string();
-    let struct_token = load_any(
-        &vm.ecal_state().db_pool,
-        &vm.ecal_state().abi,
-        struct_name,
-        type_id as usize,
-    );
-    let output_bytes = ABIEncoder::encode(&vec![struct_token]).unwrap().resolve(0);
+        .map(str::to_string)
+        .ok_or_else(|| EcalError::Unsupported {
+            type_id,
+            reason: format!("can only load structs, not '{}'", decl.type_field),
+        })
+}
+
+// Charges the queries loading the values stored by id, see `load_by_id`. They
+// run while the rows holding the ids are converted, a query per value, so
+// the gas is charged to the VM once the load is done, and the load stops
+// when it exceeds `budget`, the gas the VM has left.
+struct Meter {
+    gas_costs: GasCosts,
+    budget: u64,
+    used: Cell<u64>,
+}
 
-    vm.allocate(output_bytes.len() as u64)?;
-    let o = MemoryRange::new(vm.registers()[RegId::HP], output_bytes.len())?;
-    vm.memory_mut()[o.usizes()].copy_from_slice(&output_bytes);
+impl Meter {
+    fn new(gas_costs: GasCosts, budget: u64) -> Self {
+        Self {
+            gas_costs,
+            budget,
+            used: Cell::new(0),
+        }
+    }
 
-    // Return the address of the output tuple through the rB register
-    vm.registers_mut()[rb] = o.start as u64;
+    // Charged before a query returning up to `rows` rows.
+    fn charge(&self, rows: u64) -> EcalResult<()> {
+        let used = self
+            .used
+            .get()
+            .saturating_add(self.gas_costs.statements(1))
+            .saturating_add(self.gas_costs.rows(rows));
+        self.used.set(used);
+        if used > self.budget {
+            return Err(PanicReason::OutOfGas.into());
+        }
+        Ok(())
+    }
 
-    Ok(())
+    fn used(&self) -> u64 {
+        self.used.get()
+    }
+}
+
+// Which rows `load_any` loads.
+struct Query {
+    filter: Filter,
+    order_by: OrderBy,
+    limit: u64,
+    offset: u64,
+}
+
+// The number of rows of a struct matching a filter.
+fn count(
+    ecal: &super::MyEcal,
+    struct_name: &str,
+    type_id: usize,
+    filter: &Filter,
+) -> EcalResult<u64> {
+    let mut args = vec![];
+    let condition = filter.condition(ecal, type_id, &format!("\"{struct_name}_0\""), &mut args)?;
+    let sql = format!(
+        "SELECT COUNT(*) AS total FROM \"{struct_name}\" AS \"{struct_name}_0\" WHERE {condition}"
+    );
+    let sql_error = |source| EcalError::Sql {
+        type_id,
+        sql: sql.clone(),
+        source,
+    };
+    let rows = ecal.db()?.fetch_all(&sql, &args).map_err(sql_error)?;
+    let total = rows
+        .first()
+        .ok_or(sqlx::Error::RowNotFound)
+        .and_then(|row| row.try_get::<i64, _>("total"))
+        .map_err(sql_error)?;
+    Ok(total as u64)
 }
 
-fn load_any(pool: &Pool<Postgres>, abi: &crate::ABI, struct_name: String, type_id: usize) -> Token {
+// Returns the values, and the number of rows matching the filter, counted by
+// the same statement so it's consistent with them. `None` if there are no
+// values.
+fn load_any(
+    ecal: &super::MyEcal,
+    meter: &Meter,
+    struct_name: &str,
+    type_id: usize,
+    query: &Query,
+) -> EcalResult<(Vec<Token>, Option<u64>)> {
+    let abi = &ecal.abi;
     let mut context = HashMap::new();
-    let (selects, joins, types) = load_any_rec(abi, HashSet::new(), &mut context, type_id as usize);
+    let (mut selects, joins, types) =
+        load_any_rec(abi, HashSet::new(), &mut context, type_id as usize);
+    // The number of matches, computed before the limit. The joins are
+    // one-to-one, so there's a row per match. It's the last column,
+    // `convert_row` doesn't read it.
+    selects.push("COUNT(*) OVER ()".to_string());
     let selects = selects.join(", ");
     let joins = joins.join(" ");
 
-    let types: Vec<ParamType> = types.iter().map(|t| abi.param_type(*t)).collect();
-    // TODO: until `load` accepts filter parameter, return a single value as a proof of concept
-    let query_string =
-        format!("SELECT {selects} FROM \"{struct_name}\" AS \"{struct_name}_0\" {joins} LIMIT 1");
+    let types: Vec<ParamType> = types
+        .iter()
+        .map(|t| super::application_param_type(abi, t))
+        .collect::<EcalResult<_>>()?;
+    let mut args = vec![];
+    let table = format!("\"{struct_name}_0\"");
+    let condition = query.filter.condition(ecal, type_id, &table, &mut args)?;
+    let order_by = query.order_by.sql(ecal, type_id, &table)?;
+    // Postgres takes BIGINT limits and offsets.
+    args.push(SqlArg::Int(query.limit.min(i64::MAX as u64) as i64));
+    let limit = args.len();
+    args.push(SqlArg::Int(query.offset.min(i64::MAX as u64) as i64));
+    let offset = args.len();
+    let query_string = format!(
+        "SELECT {selects} FROM \"{struct_name}\" AS {table} {joins} WHERE {condition} ORDER BY {order_by} LIMIT ${limit} OFFSET ${offset}"
+    );
 
     println!("LOAD_QUERY_STRING:\n{query_string}");
 
-    let query = sqlx::query(&query_string);
-
-    // TODO: handle empty result
-    let row: sqlx::postgres::PgRow = futures::executor::block_on(query.fetch_one(pool)).unwrap();
-
-    println!("RESULT ROW IS_EMPTY={}", row.is_empty());
-
-    let mut tokens = VecDeque::new();
-    for (index, t) in types.iter().enumerate() {
-        let tok = match t {
-            ParamType::U8 => Token::U8(row.get::<i8, usize>(index) as u8),
-            ParamType::U16 => Token::U16(row.get::<i16, usize>(index) as u16),
-            ParamType::U32 => Token::U32(row.get::<i32, usize>(index) as u32),
-            ParamType::U64 => Token::U64(row.get::<i64, usize>(index) as u64),
-            ParamType::B256 => Token::B256(
-                hex::decode(row.get::<String, usize>(index))
-                    .expect("decode hex to bytes")
-                    .try_into()
-                    .expect("convert bytes to [u8;32]"),
-            ),
-
-            ParamType::Bool => Token::Bool(row.get::<bool, usize>(index)),
-            ParamType::U256 => {
-                let x: Vec<u8> = hex::decode(row.get::<String, usize>(index))
-                    .expect("decode hex to bytes")
-                    .into();
-                let y: [u8; 32] = x.try_into().unwrap();
-                Token::U256(y.into())
+    let rows = ecal
+        .db()?
+        .fetch_all(&query_string, &args)
+        .map_err(|source| EcalError::Sql {
+            type_id,
+            sql: query_string.clone(),
+            source,
+        })?;
+    let total = match rows.first() {
+        Some(row) => Some(
+            row.try_get::<i64, _>(row.len() - 1)
+                .map_err(|source| EcalError::Sql {
+                    type_id,
+                    sql: query_string.clone(),
+                    source,
+                })? as u64,
+        ),
+        None => None,
+    };
+    let values = rows
+        .iter()
+        .map(|row| convert_row(ecal, meter, type_id, row, &types, &query_string))
+        .collect::<EcalResult<_>>()?;
+    Ok((values, total))
+}
+
+// Converts a row selected with the columns of `types`, see `load_any_rec`,
+// to the value of `type_id`.
+fn convert_row(
+    ecal: &super::MyEcal,
+    meter: &Meter,
+    type_id: usize,
+    row: &PgRow,
+    types: &[ParamType],
+    sql: &str,
+) -> EcalResult<Token> {
+    let decl = ecal.abi.type_declaration(type_id);
+    convert(
+        ecal,
+        meter,
+        &mut 0,
+        row,
+        decl,
+        &mut types.iter().cloned().collect(),
+    )
+    .map_err(|reason| EcalError::Decode {
+        type_id: Some(type_id),
+        reason: format!("{reason} in the result of {sql}"),
+    })
+}
+
+fn convert(
+    ecal: &super::MyEcal,
+    meter: &Meter,
+    index: &mut usize,
+    row: &PgRow,
+    decl: TypeDeclaration,
+    params: &mut VecDeque<ParamType>,
+) -> Result<Token, String> {
+    let abi = &ecal.abi;
+    // println!("CONVERT: {index} {decl:#?} {params:#?}");
+    if decl.is_struct() && !decl.is_std_scalar() {
+        let struct_name = decl.struct_or_enum_name().unwrap();
+        let mut target_value = vec![];
+        for field in decl.components.unwrap().iter() {
+            let field_decl = abi.type_declaration(field.type_id);
+            // Nested structs and enums are joined, options of them and
+            // arrays are loaded by the ids selected for them, see
+            // `select_columns`.
+            let field_tokens = if let Some(some) = column_option(abi, field) {
+                load_column_option(ecal, meter, index, row, &some, params)?
+            } else if field_decl.is_option() {
+                let (param_type, id) = read_id(row, index, params)?;
+                load_reference(ecal, meter, field, &param_type, id)?
+            } else if field_decl.is_array() && !field_decl.is_byte_array(abi) {
+                let (param_type, id) = read_id(row, index, params)?;
+                load_array(ecal, meter, &struct_name, field, &param_type, id)?
+            } else {
+                convert(ecal, meter, index, row, field_decl, params)?
+            };
+            target_value.push(field_tokens)
+        }
+        Ok(Token::Struct(target_value))
+    } else if decl.is_tuple() {
+        let mut elements = vec![];
+        for elt in decl.components.unwrap().iter() {
+            let elt_decl = abi.type_declaration(elt.type_id);
+            elements.push(convert(ecal, meter, index, row, elt_decl, params)?);
+        }
+        Ok(Token::Tuple(elements))
+    } else if decl.type_field == "()" {
+        // No column, see `select_columns`.
+        Ok(Token::Unit)
+    } else if decl.is_fieldless_enum(abi) {
+        // The variant name, selected as text.
+        let Some(ParamType::Enum { variants, .. }) = params.pop_front() else {
+            return Err(format!("expected the columns of {}", decl.type_field));
+        };
+        let name = row
+            .try_get::<String, usize>(*index)
+            .map_err(|e| e.to_string())?;
+        let discriminant = decl
+            .components
+            .unwrap()
+            .iter()
+            .position(|variant| variant.name == name)
+            .ok_or_else(|| format!("{} has no variant '{name}'", decl.type_field))?;
+        *index += 1;
+        Ok(Token::Enum(Box::new((
+            discriminant as u64,
+            Token::Unit,
+            variants,
+        ))))
+    } else if decl.is_enum() && !decl.is_option() {
+        // The variant name, and the columns of the variants, see
+        // `select_variants`.
+        let Some(ParamType::Enum { variants, .. }) = params.pop_front() else {
+            return Err(format!("expected the columns of {}", decl.type_field));
+        };
+        let name = row
+            .try_get::<String, usize>(*index)
+            .map_err(|e| e.to_string())?;
+        *index += 1;
+        let mut active = None;
+        for (n, variant) in decl.components.as_ref().unwrap().iter().enumerate() {
+            let variant_decl = abi.type_declaration(variant.type_id);
+            if variant.name != name {
+                // The columns of the other variants are NULL.
+                let mut skipped = vec![];
+                select_columns(abi, "", &variant.name, variant, &mut vec![], &mut skipped);
+                for _ in skipped {
+                    params.pop_front();
+                    *index += 1;
+                }
+            } else if let Some(some) = column_option(abi, variant) {
+                let value = load_column_option(ecal, meter, index, row, &some, params)?;
+                active = Some((n as u64, value));
+            } else if is_reference(abi, &variant_decl) {
+                let (param_type, id) = read_id(row, index, params)?;
+                active = Some((
+                    n as u64,
+                    load_reference(ecal, meter, variant, &param_type, id)?,
+                ));
+            } else {
+                let value = convert(ecal, meter, index, row, variant_decl, params)?;
+                active = Some((n as u64, value));
             }
-            _ => unimplemented!("{t:#?}"),
+        }
+        let (discriminant, value) =
+            active.ok_or_else(|| format!("{} has no variant '{name}'", decl.type_field))?;
+        Ok(Token::Enum(Box::new((discriminant, value, variants))))
+    } else {
+        let param_type = params.pop_front().ok_or("ran out of columns")?;
+        let field_token = decode_column(row, *index, &param_type)?;
+        *index += 1;
+        Ok(field_token)
+    }
+}
+
+// Reads a column selected for a value loaded by id, see `select_columns`.
+fn read_id(
+    row: &PgRow,
+    index: &mut usize,
+    params: &mut VecDeque<ParamType>,
+) -> Result<(ParamType, Option<i64>), String> {
+    let param_type = params.pop_front().ok_or("ran out of columns")?;
+    let id = row
+        .try_get::<Option<i64>, usize>(*index)
+        .map_err(|e| e.to_string())?;
+    *index += 1;
+    Ok((param_type, id))
+}
+
+// Reads an option stored in the column of `Some`, see `column_option`. A
+// NULL is `None`.
+fn load_column_option(
+    ecal: &super::MyEcal,
+    meter: &Meter,
+    index: &mut usize,
+    row: &PgRow,
+    some: &TypeApplication,
+    params: &mut VecDeque<ParamType>,
+) -> Result<Token, String> {
+    let Some(ParamType::Enum { variants, .. }) = params.pop_front() else {
+        return Err("expected the column of an option".to_string());
+    };
+    let is_null = row
+        .try_get_raw(*index)
+        .map_err(|e| e.to_string())?
+        .is_null();
+    if is_null {
+        *index += 1;
+        return Ok(Token::Enum(Box::new((0, Token::Unit, variants))));
+    }
+    params.push_front(variants.param_types()[1].clone());
+    let some_decl = ecal.abi.type_declaration(some.type_id);
+    let value = convert(ecal, meter, index, row, some_decl, params)?;
+    Ok(Token::Enum(Box::new((1, value, variants))))
+}
+
+// Loads a value stored as the id of its row, see `is_reference`. A NULL id
+// is `None`.
+fn load_reference(
+    ecal: &super::MyEcal,
+    meter: &Meter,
+    application: &TypeApplication,
+    param_type: &ParamType,
+    id: Option<i64>,
+) -> Result<Token, String> {
+    let decl = ecal.abi.type_declaration(application.type_id);
+    if decl.is_option() {
+        let ParamType::Enum { variants, .. } = param_type else {
+            return Err(format!("expected the columns of {}", decl.type_field));
+        };
+        // The type argument is the concrete type of `Some`.
+        let some = &application.type_arguments.as_ref().unwrap()[0];
+        let value = match id {
+            Some(id) => (
+                1,
+                load_by_id(ecal, meter, some.type_id, id).map_err(|e| e.to_string())?,
+            ),
+            None => (0, Token::Unit),
         };
-        tokens.push_back(tok);
+        return Ok(Token::Enum(Box::new((value.0, value.1, variants.clone()))));
+    }
+    let id = id.ok_or_else(|| format!("no row for a {}", decl.type_field))?;
+    load_by_id(ecal, meter, application.type_id, id).map_err(|e| e.to_string())
+}
+
+// Loads the elements of an array of entities, `[Option<T>; N]`, through the
+// table linking them to the struct `parent` holding the array, see
+// `SQLTableBuilder::process_param_type`. Elements without a link are `None`.
+fn load_array(
+    ecal: &super::MyEcal,
+    meter: &Meter,
+    parent: &str,
+    field: &TypeApplication,
+    param_type: &ParamType,
+    parent_id: Option<i64>,
+) -> Result<Token, String> {
+    let decl = ecal.abi.type_declaration(field.type_id);
+    let ParamType::Array(elt, len) = param_type else {
+        return Err(format!("expected the columns of {}", decl.type_field));
+    };
+    let ParamType::Enum { variants, .. } = elt.as_ref() else {
+        return Err(format!(
+            "loading '{}' of type '{}', only arrays of options are stored",
+            field.name, decl.type_field
+        ));
+    };
+    let mut elements = vec![Token::Enum(Box::new((0, Token::Unit, variants.clone()))); *len];
+    let Some(parent_id) = parent_id else {
+        return Ok(Token::Array(elements));
+    };
+    // The type argument of the option is the concrete type of `Some`.
+    let some = decl.components.as_ref().unwrap()[0]
+        .type_arguments
+        .as_ref()
+        .unwrap()[0]
+        .type_id;
+    let sql = format!(
+        "SELECT position, id FROM \"{table}\" WHERE {parent_column} = $1",
+        table = array_table_name(&field.name),
+        parent_column = array_parent_column(parent)
+    );
+    // A link per position at most.
+    meter.charge(*len as u64).map_err(|e| e.to_string())?;
+    let db = ecal.db().map_err(|e| e.to_string())?;
+    let rows = db
+        .fetch_all(&sql, &[SqlArg::Int(parent_id)])
+        .map_err(|e| format!("{e} in {sql}"))?;
+    for row in rows {
+        let position = row
+            .try_get::<i32, _>("position")
+            .map_err(|e| e.to_string())?;
+        let id = row.try_get::<i64, _>("id").map_err(|e| e.to_string())?;
+        let element = elements
+            .get_mut(position as usize)
+            .ok_or_else(|| format!("position {position} of '{}' is out of bounds", field.name))?;
+        let value = load_by_id(ecal, meter, some, id).map_err(|e| e.to_string())?;
+        *element = Token::Enum(Box::new((1, value, variants.clone())));
     }
+    Ok(Token::Array(elements))
+}
 
-    let decl = abi.type_declaration(type_id as usize);
+// Loads the row `id` of the table of a struct or an enum.
+fn load_by_id(ecal: &super::MyEcal, meter: &Meter, type_id: usize, id: i64) -> EcalResult<Token> {
+    let abi = &ecal.abi;
+    let decl = abi.type_declaration(type_id);
+    let name = decl
+        .struct_or_enum_name()
+        .filter(|_| !decl.is_option() && !decl.is_fieldless_enum(abi))
+        .ok_or_else(|| EcalError::Unsupported {
+            type_id,
+            reason: format!("loading '{}' by id", decl.type_field),
+        })?;
+    let (selects, joins, types) = if decl.is_struct() {
+        load_any_rec(abi, HashSet::new(), &mut HashMap::new(), type_id)
+    } else {
+        let (mut selects, mut types) = (vec![], vec![]);
+        select_variants(abi, &format!("{name}_0"), type_id, &mut selects, &mut types);
+        (selects, vec![], types)
+    };
+    let types: Vec<ParamType> = types
+        .iter()
+        .map(|t| super::application_param_type(abi, t))
+        .collect::<EcalResult<_>>()?;
+    let sql = format!(
+        "SELECT {} FROM \"{name}\" AS \"{name}_0\" {} WHERE \"{name}_0\".id = $1",
+        selects.join(", "),
+        joins.join(" ")
+    );
+    let sql_error = |source| EcalError::Sql {
+        type_id,
+        sql: sql.clone(),
+        source,
+    };
+    meter.charge(1)?;
+    let rows = ecal
+        .db()?
+        .fetch_all(&sql, &[SqlArg::Int(id)])
+        .map_err(sql_error)?;
+    let row = rows
+        .first()
+        .ok_or(sqlx::Error::RowNotFound)
+        .map_err(sql_error)?;
+    convert_row(ecal, meter, type_id, row, &types, &sql)
+}
 
-    fn convert(
-        abi: &crate::ABI,
-        index: &mut usize,
+fn decode_column(
+    row: &sqlx::postgres::PgRow,
+    index: usize,
+    param_type: &ParamType,
+) -> Result<Token, String> {
+    fn hex_bytes<const N: usize>(
         row: &sqlx::postgres::PgRow,
-        decl: TypeDeclaration,
-        params: &mut VecDeque<ParamType>,
-    ) -> Token {
-        // println!("CONVERT: {index} {decl:#?} {params:#?}");
-        if decl.is_struct() && !decl.is_u256() {
-            let mut target_value = vec![];
-            for field in decl.components.unwrap().iter() {
-                let field_decl = abi.type_declaration(field.type_id);
-                let field_tokens = convert(abi, index, row, field_decl, params);
-                target_value.push(field_tokens)
+        index: usize,
+    ) -> Result<[u8; N], String> {
+        let x = row
+            .try_get::<String, usize>(index)
+            .map_err(|e| e.to_string())?;
+        let y = hex::decode(&x).map_err(|e| format!("decode hex to bytes: {e}"))?;
+        TryInto::<[u8; N]>::try_into(y).map_err(|_| format!("convert bytes to [u8;{N}]"))
+    }
+
+    fn text(row: &sqlx::postgres::PgRow, index: usize) -> Result<String, String> {
+        row.try_get::<String, usize>(index)
+            .map_err(|e| e.to_string())
+    }
+
+    fn bytea(row: &sqlx::postgres::PgRow, index: usize) -> Result<Vec<u8>, String> {
+        row.try_get::<Vec<u8>, usize>(index)
+            .map_err(|e| e.to_string())
+    }
+
+    // The columns of u8 and u16 are INTEGER.
+    fn integer<T: TryFrom<i32>>(row: &sqlx::postgres::PgRow, index: usize) -> Result<T, String> {
+        let x = row
+            .try_get::<i32, usize>(index)
+            .map_err(|e| e.to_string())?;
+        T::try_from(x).map_err(|_| format!("{x} is out of range"))
+    }
+
+    let tok = match param_type {
+        ParamType::U8 => Token::U8(integer(row, index)?),
+        ParamType::U16 => Token::U16(integer(row, index)?),
+        ParamType::U32 => {
+            let x = row
+                .try_get::<i64, usize>(index)
+                .map_err(|e| e.to_string())?;
+            Token::U32(u32::try_from(x).map_err(|_| format!("{x} is out of range"))?)
+        }
+        // NUMERIC(20), selected as text, see `load_any_rec`.
+        ParamType::U64 => {
+            let x = row
+                .try_get::<String, usize>(index)
+                .map_err(|e| e.to_string())?;
+            Token::U64(x.parse().map_err(|e| format!("'{x}' is not a u64: {e}"))?)
+        }
+        ParamType::U128 => Token::U128(u128::from_be_bytes(hex_bytes(row, index)?)),
+        ParamType::String => Token::String(text(row, index)?),
+        ParamType::StringArray(len) => {
+            Token::StringArray(StaticStringToken::new(text(row, index)?, Some(*len)))
+        }
+        ParamType::StringSlice => {
+            Token::StringSlice(StaticStringToken::new(text(row, index)?, None))
+        }
+        ParamType::Bytes => Token::Bytes(bytea(row, index)?),
+        // The words, big-endian.
+        ParamType::RawSlice => {
+            let bytes = bytea(row, index)?;
+            if bytes.len() % 8 != 0 {
+                return Err(format!(
+                    "{} bytes are not a raw slice of words",
+                    bytes.len()
+                ));
             }
-            Token::Struct(target_value)
-        } else {
-            let field_token = match params.pop_front().unwrap() {
-                ParamType::U32 => Token::U32(row.get::<i32, usize>(*index) as u32),
-                ParamType::U64 => Token::U64(row.get::<i64, usize>(*index) as u64),
-                ParamType::B256 => Token::B256(
-                    hex::decode(row.get::<String, usize>(*index))
-                        .expect("decode hex to bytes")
-                        .try_into()
-                        .expect("convert bytes to [u8;32]"),
-                ),
-                ParamType::U256 => {
-                    let x = row.get::<String, usize>(*index);
-                    let y = hex::decode(&x).expect("decode hex to bytes");
-                    let z = TryInto::<[u8; 32]>::try_into(y).unwrap();
-                    Token::U256(z.into())
-                }
-                ParamType::Bool => Token::Bool(row.get::<bool, usize>(*index)),
-                _ => unimplemented!(),
-            };
-            *index += 1;
-            field_token
+            Token::RawSlice(
+                bytes
+                    .chunks_exact(8)
+                    .map(|chunk| u64::from_be_bytes(chunk.try_into().unwrap()))
+                    .collect(),
+            )
         }
+        ParamType::Vector(elt) => Token::Vector(decode_vector(elt, bytea(row, index)?)?),
+        ParamType::B256 => Token::B256(hex_bytes(row, index)?),
+        ParamType::Bool => Token::Bool(
+            row.try_get::<bool, usize>(index)
+                .map_err(|e| e.to_string())?,
+        ),
+        ParamType::U256 => Token::U256(hex_bytes(row, index)?.into()),
+        // [Option<u8>; N], stored as the bytes of its `Some` prefix.
+        ParamType::Array(elt, len) => match elt.as_ref() {
+            ParamType::Enum { variants, .. }
+                if variants.param_types() == [ParamType::Unit, ParamType::U8] =>
+            {
+                let bytes = bytea(row, index)?;
+                if bytes.len() > *len {
+                    return Err(format!(
+                        "{} bytes don't fit in [Option<u8>; {len}]",
+                        bytes.len()
+                    ));
+                }
+                let some = bytes.into_iter().map(|b| (1, Token::U8(b)));
+                let none = std::iter::repeat_with(|| (0, Token::Unit));
+                Token::Array(
+                    some.chain(none)
+                        .take(*len)
+                        .map(|(discriminant, tok)| {
+                            Token::Enum(Box::new((discriminant, tok, variants.clone())))
+                        })
+                        .collect(),
+                )
+            }
+            _ => return Err(format!("loading {param_type:?} is not supported")),
+        },
+        _ => return Err(format!("loading {param_type:?} is not supported")),
+    };
+    Ok(tok)
+}
+
+// See `ecal_save::vector_bytes`.
+fn decode_vector(elt: &ParamType, bytes: Vec<u8>) -> Result<Vec<Token>, String> {
+    if *elt == ParamType::U8 {
+        return Ok(bytes.into_iter().map(Token::U8).collect());
+    }
+    if bytes.len() < 8 {
+        return Err("the vector has no length".to_string());
+    }
+    let (len, encoded) = bytes.split_at(8);
+    let len = u64::from_be_bytes(len.try_into().unwrap()) as usize;
+    match ABIDecoder::new(super::DECODER_CONFIG)
+        .decode(&ParamType::Array(Box::new(elt.clone()), len), encoded)
+        .map_err(|e| format!("decode Vec<{elt:?}>: {e}"))?
+    {
+        Token::Array(elements) => Ok(elements),
+        tok => Err(format!("expected an array but got {tok:?}")),
+    }
+}
+
+// Values stored as the id of their row, in the tables of their types.
+fn is_reference(abi: &crate::ABI, decl: &TypeDeclaration) -> bool {
+    decl.is_option()
+        || ((decl.is_struct() || decl.is_enum())
+            && !decl.is_std_scalar()
+            && !decl.is_fieldless_enum(abi))
+}
+
+// Selects the variant of an enum, and the columns of its variants, see
+// `SQLTableBuilder::process_enum`.
+fn select_variants(
+    abi: &crate::ABI,
+    alias: &str,
+    type_id: usize,
+    selects: &mut Vec<String>,
+    types: &mut Vec<TypeApplication>,
+) {
+    let decl = abi.type_declaration(type_id);
+    selects.push(format!("\"{alias}\".{ENUM_VARIANT_COLUMN}"));
+    types.push(TypeApplication {
+        name: String::new(),
+        type_id,
+        type_arguments: None,
+    });
+    for variant in decl.components.iter().flatten() {
+        select_columns(abi, alias, &variant.name, variant, selects, types);
     }
+}
 
-    convert(abi, &mut 0, &row, decl, &mut types.into())
+// Selects the columns of a field stored in columns, and pushes the types of
+// their values, see `ecal_save::field_columns`.
+fn select_columns(
+    abi: &crate::ABI,
+    alias: &str,
+    name: &str,
+    field: &TypeApplication,
+    selects: &mut Vec<String>,
+    types: &mut Vec<TypeApplication>,
+) {
+    let decl = abi.type_declaration(field.type_id);
+    if decl.type_field == "()" {
+        return;
+    }
+    // The id of the row holding the array, to find the elements by, see
+    // `load_array`.
+    if decl.is_array() && !decl.is_byte_array(abi) {
+        selects.push(format!("\"{alias}\".id::bigint"));
+        types.push(field.clone());
+        return;
+    }
+    // The column of `Some`, see `load_column_option`.
+    if let Some(some) = column_option(abi, field) {
+        select_columns(abi, alias, name, &some, selects, &mut vec![]);
+        types.push(field.clone());
+        return;
+    }
+    // The id of the row of the value, see `load_reference`.
+    if is_reference(abi, &decl) {
+        selects.push(format!("\"{alias}\".\"{name}Id\""));
+        types.push(field.clone());
+        return;
+    }
+    if decl.is_tuple() {
+        for (i, elt) in decl.components.iter().flatten().enumerate() {
+            let name = tuple_element_column(name, i);
+            select_columns(abi, alias, &name, elt, selects, types);
+        }
+        return;
+    }
+    // sqlx can't decode NUMERIC, or custom types, without extra features.
+    let cast = if decl.type_field == "u64" || decl.is_fieldless_enum(abi) {
+        "::text"
+    } else {
+        ""
+    };
+    let stmt = format!("\"{alias}\".\"{name}\"{cast}");
+    println!("load_any_rec select={stmt}");
+    selects.push(stmt);
+    types.push(field.clone());
 }
 
 fn load_any_rec(
@@ -141,14 +811,14 @@ fn load_any_rec(
     mut unique_joins: HashSet<String>,
     context: &mut HashMap<String, usize>,
     type_id: usize,
-) -> (Vec<String>, Vec<String>, Vec<usize>) {
+) -> (Vec<String>, Vec<String>, Vec<TypeApplication>) {
     println!("load_any_rec unique_joins={unique_joins:#?}");
     let decl = abi.type_declaration(type_id);
 
     let mut struct_columns: BTreeMap<String, Vec<String>> = BTreeMap::new();
     let mut selects: Vec<String> = vec![];
     let mut joins: Vec<String> = vec![];
-    let mut types: Vec<usize> = vec![];
+    let mut types: Vec<TypeApplication> = vec![];
 
     let struct_name = decl.type_field.strip_prefix("struct ").unwrap().to_string();
     let columns: Vec<String> = decl
@@ -162,7 +832,7 @@ fn load_any_rec(
 
     for field in decl.components.as_ref().unwrap().iter() {
         let field_decl = abi.type_declaration(field.type_id);
-        if field_decl.is_struct() && !field_decl.is_u256() {
+        if field_decl.is_struct() && !field_decl.is_std_scalar() {
             // println!("FOO");
             let field_struct_name = field_decl.type_field.strip_prefix("struct ").unwrap();
             let i = context
@@ -185,18 +855,531 @@ fn load_any_rec(
             selects.extend(nested_selects);
             joins.extend(nested_joins);
             types.extend(nested_types);
+        } else if field_decl.is_enum()
+            && !field_decl.is_option()
+            && !field_decl.is_fieldless_enum(abi)
+        {
+            let enum_name = field_decl.struct_or_enum_name().unwrap();
+            let i = *context
+                .entry(enum_name.clone())
+                .and_modify(|x| *x += 1)
+                .or_insert(0);
+            let enum_alias = format!("{enum_name}_{i}");
+            let j = context.get(&struct_name).unwrap_or(&0);
+            if unique_joins.insert(enum_alias.clone()) {
+                let stmt = format!("LEFT JOIN \"{enum_name}\" AS \"{enum_alias}\" ON \"{struct_name}_{j}\".\"{field_name}Id\" = \"{enum_alias}\".id", field_name = field.name);
+                joins.push(stmt);
+            }
+            select_variants(abi, &enum_alias, field.type_id, &mut selects, &mut types);
         } else {
             println!("BAR");
             let i = context.get(&struct_name).unwrap_or(&0);
-            let stmt = format!(
-                "\"{struct_name}_{i}\".\"{field_name}\"",
-                field_name = field.name
-            );
-            println!("load_any_rec select={stmt}");
-            selects.push(stmt);
-            types.push(field.type_id);
+            let alias = format!("{struct_name}_{i}");
+            select_columns(abi, &alias, &field.name, field, &mut selects, &mut types);
         }
     }
 
     (selects, joins, types)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::ecal_delete::delete_rows;
+    use super::super::ecal_save::{fetch_id, fetch_ids, vector_bytes};
+    use super::super::filter::Op;
+    use super::super::{EcalMode, ErrorReport, GasCosts, MyEcal};
+    use super::*;
+    use crate::db::{Db, DbConfig};
+    use crate::sql::sql_table_builder::{EntityKeys, PrimaryKeys, SQLTableBuilder};
+    use fuels::types::U256;
+
+    // Runs `f` in a transaction that is rolled back. Skipped without
+    // DATABASE_URL, see `DbConfig::from_env`.
+    fn with_db(f: impl FnOnce(&Db)) {
+        if std::env::var("DATABASE_URL").is_err() {
+            println!("DATABASE_URL is not set, skipping");
+            return;
+        }
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (_pool, db) = runtime.block_on(DbConfig::from_env().connect()).unwrap();
+        let tx = db.begin().unwrap();
+        f(&tx);
+        tx.rollback().unwrap();
+    }
+
+    // A struct with a field of every scalar type, like `Scalars` in the
+    // mystruct-indexer script.
+    const ROUND_TRIP: usize = 8;
+    // A struct with options stored in columns, see `column_option`.
+    const OPTIONS: usize = 21;
+    // A struct with a nested struct, an enum, an option and an array of
+    // `RoundTripItem`s, stored in rows of their own.
+    const ITEM: usize = 22;
+    const ENTITY: usize = 25;
+
+    fn round_trip_abi() -> crate::ABI {
+        let field = |name: &str, type_id: usize| serde_json::json!({"name": name, "type": type_id, "typeArguments": null});
+        let applied = |name: &str, type_id: usize, args: Vec<serde_json::Value>| serde_json::json!({"name": name, "type": type_id, "typeArguments": args});
+        let decl = |type_id: usize, type_field: &str, components: serde_json::Value| {
+            serde_json::json!({
+                "typeId": type_id,
+                "type": type_field,
+                "components": components,
+                "typeParameters": null
+            })
+        };
+        // Generic over `T`, the type 18.
+        let generic = |type_id: usize, type_field: &str, components: serde_json::Value| {
+            serde_json::json!({
+                "typeId": type_id,
+                "type": type_field,
+                "components": components,
+                "typeParameters": [18]
+            })
+        };
+        let types = vec![
+            decl(0, "()", serde_json::Value::Null),
+            decl(
+                1,
+                "(_, _)",
+                serde_json::json!([field("__tuple_element", 10), field("__tuple_element", 3)]),
+            ),
+            decl(2, "b256", serde_json::Value::Null),
+            decl(3, "bool", serde_json::Value::Null),
+            decl(
+                4,
+                "enum RoundTripColor",
+                serde_json::json!([field("Red", 0), field("Green", 0)]),
+            ),
+            decl(5, "raw untyped ptr", serde_json::Value::Null),
+            decl(6, "str[5]", serde_json::Value::Null),
+            decl(
+                7,
+                "struct Bytes",
+                serde_json::json!([field("buf", 13), field("len", 10)]),
+            ),
+            decl(
+                ROUND_TRIP,
+                "struct RoundTrip",
+                serde_json::json!([
+                    field("a", 16),
+                    field("b", 12),
+                    field("c", 15),
+                    field("d", 10),
+                    field("e", 14),
+                    field("f", 2),
+                    field("g", 3),
+                    field("h", 6),
+                    field("i", 1),
+                    field("j", 0),
+                    field("k", 4),
+                    field("l", 11),
+                    field("m", 9),
+                    field("n", 7),
+                ]),
+            ),
+            decl(9, "struct String", serde_json::json!([field("bytes", 7)])),
+            decl(10, "u64", serde_json::Value::Null),
+            decl(
+                11,
+                "struct U128",
+                serde_json::json!([field("upper", 10), field("lower", 10)]),
+            ),
+            decl(12, "u16", serde_json::Value::Null),
+            decl(
+                13,
+                "struct RawBytes",
+                serde_json::json!([field("ptr", 5), field("cap", 10)]),
+            ),
+            decl(14, "u256", serde_json::Value::Null),
+            decl(15, "u32", serde_json::Value::Null),
+            decl(16, "u8", serde_json::Value::Null),
+            generic(
+                17,
+                "enum Option",
+                serde_json::json!([field("None", 0), field("Some", 18)]),
+            ),
+            decl(18, "generic T", serde_json::Value::Null),
+            generic(
+                19,
+                "struct RawVec",
+                serde_json::json!([field("ptr", 5), field("cap", 10)]),
+            ),
+            generic(
+                20,
+                "struct Vec",
+                serde_json::json!([applied("buf", 19, vec![field("", 18)]), field("len", 10)]),
+            ),
+            decl(
+                OPTIONS,
+                "struct RoundTripOptions",
+                serde_json::json!([
+                    applied("bytes", 17, vec![field("", 7)]),
+                    applied("vec", 17, vec![applied("", 20, vec![field("", 16)])]),
+                    applied("string", 17, vec![field("", 9)]),
+                    applied("color", 17, vec![field("", 4)]),
+                ]),
+            ),
+            decl(
+                ITEM,
+                "struct RoundTripItem",
+                serde_json::json!([field("value", 10)]),
+            ),
+            decl(
+                23,
+                "enum RoundTripShape",
+                serde_json::json!([field("Empty", 0), field("Item", ITEM), field("Count", 10)]),
+            ),
+            decl(
+                24,
+                "[_; 2]",
+                serde_json::json!([applied("__array_element", 17, vec![field("", ITEM)])]),
+            ),
+            decl(
+                ENTITY,
+                "struct RoundTripEntity",
+                serde_json::json!([
+                    field("number", 10),
+                    field("inner", ITEM),
+                    field("shape", 23),
+                    applied("maybe", 17, vec![field("", ITEM)]),
+                    field("items", 24),
+                ]),
+            ),
+        ];
+        let abi = serde_json::json!({
+            "types": types,
+            "functions": [],
+            "loggedTypes": [],
+            "messagesTypes": [],
+            "configurables": []
+        });
+        let path = std::env::temp_dir().join(format!("round-trip-abi-{}.json", std::process::id()));
+        std::fs::write(&path, abi.to_string()).unwrap();
+        crate::abi::parse_abi(path.to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn scalars_round_trip_through_the_database() {
+        let abi = round_trip_abi();
+        let ParamType::Enum { variants, .. } = abi.param_type(4) else {
+            panic!("expected an enum");
+        };
+        let fields = vec![
+            ("u8", Token::U8(255)),
+            ("u16", Token::U16(65535)),
+            ("u32", Token::U32(u32::MAX)),
+            ("u64", Token::U64(u64::MAX)),
+            ("u256", Token::U256(U256::MAX)),
+            ("b256", Token::B256([0xab; 32])),
+            ("bool", Token::Bool(true)),
+            (
+                "str[5]",
+                Token::StringArray(StaticStringToken::new("hello".to_string(), Some(5))),
+            ),
+            (
+                "(u64, bool)",
+                Token::Tuple(vec![Token::U64(42), Token::Bool(true)]),
+            ),
+            ("()", Token::Unit),
+            (
+                "enum RoundTripColor",
+                Token::Enum(Box::new((1, Token::Unit, variants))),
+            ),
+            ("U128", Token::U128(u128::MAX - 1)),
+            ("String", Token::String("hello".to_string())),
+            ("Bytes", Token::Bytes(vec![0, 1, 255])),
+        ];
+        let value = Token::Struct(fields.iter().map(|(_, tok)| tok.clone()).collect());
+
+        with_schema(&abi, |ecal| {
+            save_values(ecal, ROUND_TRIP, &[value.clone()]);
+            let (loaded, total) = load_values(ecal, "RoundTrip", ROUND_TRIP, Filter::Any);
+            assert_eq!(total, Some(1));
+            let [Token::Struct(loaded)] = loaded.as_slice() else {
+                panic!("expected one struct, got {loaded:#?}");
+            };
+            for ((type_name, saved), loaded) in fields.iter().zip(loaded) {
+                assert_eq!(loaded, saved, "{type_name}");
+            }
+        });
+    }
+
+    #[test]
+    fn column_options_round_trip_through_the_database() {
+        let abi = round_trip_abi();
+        let ParamType::Enum { variants, .. } = abi.param_type(4) else {
+            panic!("expected an enum");
+        };
+        let green = Token::Enum(Box::new((1, Token::Unit, variants)));
+        let ParamType::Struct { fields, .. } = abi.param_type(OPTIONS) else {
+            panic!("expected a struct");
+        };
+        let option = |n: usize, value: Option<Token>| {
+            let ParamType::Enum { variants, .. } = fields[n].clone() else {
+                panic!("expected an option");
+            };
+            let (discriminant, value) = match value {
+                Some(value) => (1, value),
+                None => (0, Token::Unit),
+            };
+            Token::Enum(Box::new((discriminant, value, variants)))
+        };
+        let some = Token::Struct(vec![
+            option(0, Some(Token::Bytes(vec![0, 1, 255]))),
+            option(1, Some(Token::Vector(vec![Token::U8(7), Token::U8(8)]))),
+            option(2, Some(Token::String("hello".to_string()))),
+            option(3, Some(green)),
+        ]);
+        let none = Token::Struct((0..4).map(|n| option(n, None)).collect());
+
+        with_schema(&abi, |ecal| {
+            let values = [some, none];
+            save_values(ecal, OPTIONS, &values);
+            let (loaded, total) = load_values(ecal, "RoundTripOptions", OPTIONS, Filter::Any);
+            assert_eq!(total, Some(2));
+            assert_eq!(loaded, values);
+        });
+    }
+
+    #[test]
+    fn entities_round_trip_through_the_database() {
+        let abi = round_trip_abi();
+        let ParamType::Struct { fields, .. } = abi.param_type(ENTITY) else {
+            panic!("expected a struct");
+        };
+        let variants = |param_type: &ParamType| {
+            let ParamType::Enum { variants, .. } = param_type else {
+                panic!("expected an enum, got {param_type:?}");
+            };
+            variants.clone()
+        };
+        let ParamType::Array(element, _) = &fields[4] else {
+            panic!("expected an array");
+        };
+        let (shapes, options, elements) = (
+            variants(&fields[2]),
+            variants(&fields[3]),
+            variants(element),
+        );
+        let item = |value: u64| Token::Struct(vec![Token::U64(value)]);
+        let some =
+            |variants: &EnumVariants, tok: Token| Token::Enum(Box::new((1, tok, variants.clone())));
+        let none =
+            |variants: &EnumVariants| Token::Enum(Box::new((0, Token::Unit, variants.clone())));
+        let entity = |number: u64, shape: (u64, Token), maybe: Token, items: Vec<Token>| {
+            Token::Struct(vec![
+                Token::U64(number),
+                item(number * 10),
+                Token::Enum(Box::new((shape.0, shape.1, shapes.clone()))),
+                maybe,
+                Token::Array(items),
+            ])
+        };
+        let values = vec![
+            entity(
+                1,
+                (1, item(7)),
+                some(&options, item(8)),
+                vec![some(&elements, item(7)), none(&elements)],
+            ),
+            entity(
+                2,
+                (2, Token::U64(3)),
+                none(&options),
+                vec![none(&elements), some(&elements, item(9))],
+            ),
+            entity(
+                3,
+                (0, Token::Unit),
+                none(&options),
+                vec![none(&elements), none(&elements)],
+            ),
+        ];
+        let number = |op: Op, n: u64| Filter::Field {
+            field: 0,
+            op,
+            value: ABIEncoder::encode(&[Token::U64(n)]).unwrap().resolve(0),
+        };
+
+        with_schema(&abi, |ecal| {
+            // In one statement, like `save_many`.
+            let mut builder = ecal.save_stmt_builder();
+            let ids: Vec<_> = values
+                .iter()
+                .map(|value| builder.save_value(ENTITY, value.clone()).unwrap())
+                .collect();
+            let stmt = builder.stmt_many(&ids);
+            fetch_ids(ecal.db().unwrap(), &stmt, &builder.args, ids.len()).unwrap();
+
+            let (loaded, total) = load_values(ecal, "RoundTripEntity", ENTITY, Filter::Any);
+            assert_eq!(total, Some(3));
+            assert_eq!(loaded, values);
+
+            let filter = Filter::And(Box::new(number(Op::Gt, 1)), Box::new(number(Op::Ne, 3)));
+            let (loaded, total) = load_values(ecal, "RoundTripEntity", ENTITY, filter);
+            assert_eq!(total, Some(1));
+            assert_eq!(loaded, values[1..2]);
+            let filter = Filter::Or(Box::new(number(Op::Le, 1)), Box::new(number(Op::Eq, 3)));
+            let (loaded, total) = load_values(ecal, "RoundTripEntity", ENTITY, filter);
+            assert_eq!(total, Some(2));
+            assert_eq!(loaded, [values[0].clone(), values[2].clone()]);
+
+            // The links to the elements of its array go with the value, the
+            // elements stay.
+            assert_eq!(delete_rows(ecal, ENTITY, &number(Op::Eq, 1)).unwrap(), 1);
+            let (loaded, _) = load_values(ecal, "RoundTripEntity", ENTITY, Filter::Any);
+            assert_eq!(loaded, values[1..]);
+            let links = ecal
+                .db()
+                .unwrap()
+                .fetch_all("SELECT id FROM \"Items\"", &[])
+                .unwrap();
+            assert_eq!(links.len(), 1);
+            // Rows other rows point to can't be deleted. This aborts the
+            // transaction, so it goes last.
+            assert!(delete_rows(ecal, ITEM, &Filter::Any).is_err());
+        });
+    }
+
+    // Runs `f` with the tables of every type of `abi`, see `with_db`.
+    fn with_schema(abi: &crate::ABI, f: impl FnOnce(&MyEcal)) {
+        with_db(|db| {
+            let mut schema = SQLTableBuilder::new(abi.clone());
+            for decl in abi.types.values() {
+                schema.process_decl(decl.clone()).unwrap();
+            }
+            for stmt in schema.statements() {
+                db.execute(&stmt, &[]).unwrap();
+            }
+            let ecal = MyEcal {
+                abi: abi.clone(),
+                db: Some(db.clone()),
+                files_root: std::env::temp_dir(),
+                errors: ErrorReport::default(),
+                gas_costs: GasCosts::default(),
+                mode: EcalMode::Live,
+                write_buffer: None,
+                primary_keys: PrimaryKeys::Serial,
+                entity_keys: EntityKeys::default(),
+            };
+            f(&ecal);
+        });
+    }
+
+    fn save_values(ecal: &MyEcal, type_id: usize, values: &[Token]) {
+        for value in values {
+            let mut builder = ecal.save_stmt_builder();
+            let (stmt, id) = builder.generate_stmt(type_id, value.clone()).unwrap();
+            fetch_id(ecal.db().unwrap(), &stmt, &builder.args, id.as_ref()).unwrap();
+        }
+    }
+
+    // The values matching `filter`, in the order they were saved.
+    fn load_values(
+        ecal: &MyEcal,
+        struct_name: &str,
+        type_id: usize,
+        filter: Filter,
+    ) -> (Vec<Token>, Option<u64>) {
+        let query = Query {
+            filter,
+            order_by: OrderBy::default(),
+            limit: MAX_PAGE,
+            offset: 0,
+        };
+        let meter = Meter::new(GasCosts::default(), u64::MAX);
+        load_any(ecal, &meter, struct_name, type_id, &query).unwrap()
+    }
+
+    #[test]
+    fn decode_column_reads_the_columns_of_the_schema() {
+        with_db(|db| {
+            let rows = db
+                .fetch_all(
+                    "SELECT 255::integer, 4294967295::bigint, 18446744073709551615::numeric(20)::text, $1::text, $2::bytea, TRUE",
+                    &[
+                        SqlArg::Text(format!("{:032x}", u128::MAX)),
+                        SqlArg::Bytes(vec![1, 2, 3]),
+                    ],
+                )
+                .unwrap();
+            let row = &rows[0];
+            let cases = vec![
+                (ParamType::U8, Token::U8(255)),
+                (ParamType::U32, Token::U32(u32::MAX)),
+                (ParamType::U64, Token::U64(u64::MAX)),
+                (ParamType::U128, Token::U128(u128::MAX)),
+                (ParamType::Bytes, Token::Bytes(vec![1, 2, 3])),
+                (ParamType::Bool, Token::Bool(true)),
+            ];
+            for (index, (param_type, expected)) in cases.into_iter().enumerate() {
+                assert_eq!(
+                    decode_column(row, index, &param_type).unwrap(),
+                    expected,
+                    "{param_type:?}"
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn decode_column_rejects_values_out_of_range() {
+        with_db(|db| {
+            let rows = db
+                .fetch_all(
+                    "SELECT 256::integer, (-1)::bigint, '-1'::text, 'abcd'::text, '\\x01020304050607'::bytea",
+                    &[],
+                )
+                .unwrap();
+            let row = &rows[0];
+            let cases = vec![
+                ParamType::U8,
+                ParamType::U32,
+                ParamType::U64,
+                ParamType::U128,
+                ParamType::RawSlice,
+            ];
+            for (index, param_type) in cases.into_iter().enumerate() {
+                assert!(
+                    decode_column(row, index, &param_type).is_err(),
+                    "{param_type:?}"
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn vectors_round_trip_through_their_bytes() {
+        let cases = vec![
+            (ParamType::U8, vec![Token::U8(1), Token::U8(255)]),
+            (ParamType::U64, vec![Token::U64(1), Token::U64(u64::MAX)]),
+            (ParamType::B256, vec![Token::B256([7; 32])]),
+            (ParamType::Bool, vec![]),
+        ];
+        for (elt, toks) in cases {
+            let bytes = vector_bytes(&elt, &toks).unwrap();
+            assert_eq!(decode_vector(&elt, bytes).unwrap(), toks, "{elt:?}");
+        }
+    }
+
+    #[test]
+    fn decode_vector_rejects_truncated_bytes() {
+        assert!(decode_vector(&ParamType::U64, vec![0; 4]).is_err());
+        // A length of 2, and one element.
+        let mut bytes = 2u64.to_be_bytes().to_vec();
+        bytes.extend(1u64.to_be_bytes());
+        assert!(decode_vector(&ParamType::U64, bytes).is_err());
+    }
+
+    #[test]
+    fn meter_charges_every_query_until_the_budget_is_spent() {
+        let gas_costs = GasCosts::default();
+        let budget = gas_costs.statements(2) + gas_costs.rows(3);
+        let meter = Meter::new(gas_costs, budget);
+        assert!(meter.charge(1).is_ok());
+        assert!(meter.charge(2).is_ok());
+        assert_eq!(meter.used(), budget);
+        assert!(matches!(meter.charge(0), Err(EcalError::Vm(_))));
+    }
+}
@@ -1,43 +1,37 @@
 use fuel_abi_types::abi::program::TypeDeclaration;
 use fuel_asm::RegId;
-use fuel_vm::{
-    error::SimpleResult,
-    prelude::{Interpreter, MemoryRange},
-};
+use fuel_vm::prelude::Interpreter;
 use fuels::core::codec::ABIDecoder;
 use fuels::types::Token;
 
-pub fn println<S, Tx>(vm: &mut Interpreter<S, Tx, super::MyEcal>, rb: RegId) -> SimpleResult<()> {
+use super::{EcalError, EcalResult};
+
+pub fn println<S, Tx>(vm: &mut Interpreter<S, Tx, super::MyEcal>, rb: RegId) -> EcalResult<()> {
     let start = std::time::Instant::now();
 
-    let (type_id, addr, size): (u64, u64, u64) = {
-        let addr = vm.registers()[rb];
-        let r = MemoryRange::new(addr, 3 * 8)?;
-        let bytes: [u8; 3 * 8] = vm.memory()[r.usizes()].try_into().unwrap();
-        fuels::core::codec::try_from_bytes(&bytes, super::DECODER_CONFIG).unwrap()
-    };
+    let [type_id, addr, size] = super::read_words(vm, rb)?;
     let type_id = type_id as usize;
 
-    let data = {
-        let r = MemoryRange::new(addr, size)?;
-        let mut bytes = Vec::with_capacity(size as usize);
-        bytes.extend_from_slice(&vm.memory()[r.usizes()]);
-        bytes
-    };
+    let data = super::read_memory(vm, addr, size)?;
+    let gas_costs = vm.ecal_state().gas_costs;
+    vm.gas_charge(gas_costs.decoding(size))?;
 
     // println!("print_any_ecal type_id = {type_id}");
 
-    let param_type = vm.ecal_state_mut().abi.param_type(type_id);
+    let param_type = super::param_type(&vm.ecal_state().abi, type_id)?;
     let tokens = ABIDecoder::new(super::DECODER_CONFIG)
         .decode(&param_type, data.as_ref())
-        .expect(&format!("{param_type:#?}"));
+        .map_err(|e| EcalError::Decode {
+            type_id: Some(type_id),
+            reason: format!("{e} {param_type:#?}"),
+        })?;
     // println!("> print_any = {tokens:?}");
-    let result = pretty_print(&vm.ecal_state().abi, type_id, tokens);
+    let result = pretty_print(&vm.ecal_state().abi, type_id, tokens)?;
 
     #[cfg(debug_assertions)]
     println!("> PRINT_ANY:");
 
-    println!("{result}");
+    output(vm, &result)?;
 
     let duration = start.elapsed();
 
@@ -46,8 +40,37 @@ pub fn println<S, Tx>(vm: &mut Interpreter<S, Tx, super::MyEcal>, rb: RegId) ->
     Ok(())
 }
 
+// ecal_lib::println(String)
+pub fn println_str<S, Tx>(vm: &mut Interpreter<S, Tx, super::MyEcal>, rb: RegId) -> EcalResult<()> {
+    let bytes = read_bytes(vm, rb)?;
+    output(vm, &String::from_utf8_lossy(&bytes))
+}
+
+// ecal_lib::println_u64(u64)
+pub fn println_u64<S, Tx>(vm: &mut Interpreter<S, Tx, super::MyEcal>, rb: RegId) -> EcalResult<()> {
+    let value = vm.registers()[rb];
+    output(vm, &value.to_string())
+}
+
+// Reads the bytes of a (ptr, len) tuple whose address is in rB.
+pub fn read_bytes<S, Tx>(
+    vm: &mut Interpreter<S, Tx, super::MyEcal>,
+    rb: RegId,
+) -> EcalResult<Vec<u8>> {
+    let [addr, len] = super::read_words(vm, rb)?;
+    super::read_memory(vm, addr, len)
+}
+
+// All output printed by scripts goes through here.
+fn output<S, Tx>(vm: &mut Interpreter<S, Tx, super::MyEcal>, text: &str) -> EcalResult<()> {
+    let gas = vm.ecal_state().gas_costs.printing(text.len() as u64);
+    vm.gas_charge(gas)?;
+    println!("{text}");
+    Ok(())
+}
+
 // Given a type id and encoded data, it pretty-prints the data.
-pub fn pretty_print(abi: &crate::ABI, type_id: usize, tok: Token) -> String {
+pub fn pretty_print(abi: &crate::ABI, type_id: usize, tok: Token) -> EcalResult<String> {
     fn pretty_print_inner(
         abi: &crate::ABI,
         indent: usize,
@@ -55,13 +78,14 @@ pub fn pretty_print(abi: &crate::ABI, type_id: usize, tok: Token) -> String {
         // For processing Option<T> types, need to pass the TypeDeclaration for T down.
         inner_decl: Option<TypeDeclaration>,
         tok: Token,
-    ) -> String {
-        match tok {
+    ) -> EcalResult<String> {
+        Ok(match tok {
             Token::Unit => "()".to_string(),
             Token::U64(x) => format!("{}", x),
             Token::U32(x) => format!("{}", x),
             Token::U16(x) => format!("{}", x),
             Token::U8(x) => format!("{}", x),
+            Token::U128(x) => format!("{}", x),
             Token::Struct(fields) => {
                 let indent = indent + 4;
                 let comps = decl.components.unwrap();
@@ -74,7 +98,7 @@ pub fn pretty_print(abi: &crate::ABI, type_id: usize, tok: Token) -> String {
                         " ".repeat(indent)
                             + &name
                             + " = "
-                            + &pretty_print_inner(abi, indent, decl, None, field),
+                            + &pretty_print_inner(abi, indent, decl, None, field)?,
                     )
                 }
 
@@ -97,7 +121,7 @@ pub fn pretty_print(abi: &crate::ABI, type_id: usize, tok: Token) -> String {
                     let type_id: usize = comps[i].type_id as usize;
                     let decl = abi.type_declaration(type_id);
                     result.push(
-                        " ".repeat(indent) + &pretty_print_inner(abi, indent, decl, None, field),
+                        " ".repeat(indent) + &pretty_print_inner(abi, indent, decl, None, field)?,
                     )
                 }
                 "(\n".to_string() + &result.join(",\n") + "\n" + &" ".repeat(indent - 4) + ")"
@@ -105,6 +129,8 @@ pub fn pretty_print(abi: &crate::ABI, type_id: usize, tok: Token) -> String {
             Token::B256(bytes) => hex::encode(bytes),
             Token::U256(value) => hex::encode(Into::<[u8; 32]>::into(value)),
             Token::Bool(b) => format!("{b}"),
+            Token::String(s) => format!("{s:?}"),
+            Token::Bytes(bytes) => hex::encode(bytes),
             Token::Array(elems) => {
                 let inner_type = &decl.components.as_ref().unwrap()[0];
                 // the inner_decl passed to the function; TODO: clean inner_decl
@@ -134,7 +160,7 @@ pub fn pretty_print(abi: &crate::ABI, type_id: usize, tok: Token) -> String {
                             tok,
                         )
                     })
-                    .collect::<Vec<String>>();
+                    .collect::<EcalResult<Vec<String>>>()?;
                 "[".to_string() + &elems.join(", ") + "]"
             }
             Token::Enum(enum_selector) => {
@@ -183,11 +209,16 @@ pub fn pretty_print(abi: &crate::ABI, type_id: usize, tok: Token) -> String {
 
                 variant
                     + "("
-                    + &pretty_print_inner(abi, indent, inner_decl.unwrap(), inner_inner_decl, y)
+                    + &pretty_print_inner(abi, indent, inner_decl.unwrap(), inner_inner_decl, y)?
                     + ")"
             }
-            _ => unimplemented!("pretty_print {tok:#?}"),
-        }
+            _ => {
+                return Err(EcalError::Unsupported {
+                    type_id: decl.type_id,
+                    reason: format!("printing {tok:?}"),
+                })
+            }
+        })
     }
     let decl = abi.type_declaration(type_id);
     pretty_print_inner(abi, 0, decl, None, tok)
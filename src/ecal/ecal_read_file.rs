@@ -1,59 +1,47 @@
 use fuel_asm::RegId;
-use fuel_vm::{
-    error::SimpleResult,
-    prelude::{Interpreter, MemoryRange},
-};
+use fuel_vm::prelude::{Interpreter, MemoryRange};
 
 use std::{
     fs::File,
     io::{Read, Seek, SeekFrom},
-    path::PathBuf,
+    path::{Component, Path, PathBuf},
 };
 
-fn read_file_ecal<S, Tx>(
-    vm: &mut Interpreter<S, Tx, Self>,
-    _ra: RegId,
-    rb: RegId,
-    _rc: RegId,
-    _rd: RegId,
-) -> SimpleResult<()> {
-    let args: (u64, u64, u64, u64) = {
-        let addr = vm.registers()[rb];
-        let r = MemoryRange::new(addr, 4 * 8)?;
-        let bytes: [u8; 4 * 8] = vm.memory()[r.usizes()].try_into().unwrap();
-        fuels::core::codec::try_from_bytes(&bytes, DECODER_CONFIG).unwrap()
-    };
+use super::{EcalError, EcalResult};
+
+pub fn read_file<S, Tx>(vm: &mut Interpreter<S, Tx, super::MyEcal>, rb: RegId) -> EcalResult<()> {
+    let [seek, len, path_ptr, path_len] = super::read_words(vm, rb)?;
 
     #[cfg(debug_assertions)]
-    println!("read_file args = {args:?}");
+    println!("read_file args = {:?}", (seek, len, path_ptr, path_len));
 
-    vm.gas_charge(args.1.saturating_add(1))?;
+    vm.gas_charge(len.saturating_add(1))?;
 
     // Extract file path from vm memory
-    let path = {
-        let r = MemoryRange::new(args.2, args.3)?;
-        let path = String::from_utf8_lossy(&vm.memory()[r.usizes()]);
-        let path = PathBuf::from(path.as_ref());
+    let path = String::from_utf8(super::read_memory(vm, path_ptr, path_len)?)?;
 
-        #[cfg(debug_assertions)]
-        println!("read_file file_path = {path:?}");
+    #[cfg(debug_assertions)]
+    println!("read_file file_path = {path:?}");
 
-        path
+    let io_error = |source| EcalError::Io {
+        path: path.clone(),
+        source,
     };
 
+    let full_path =
+        sandboxed_path(&vm.ecal_state().files_root, &path).map_err(EcalError::Sandbox)?;
+
     // Seek file to correct position
-    let mut file = File::open(path).map_err(|_| PanicReason::EcalError)?;
-    let _ = file
-        .seek(SeekFrom::Start(args.0))
-        .map_err(|_| PanicReason::EcalError)?;
+    let mut file = File::open(full_path).map_err(io_error)?;
+    file.seek(SeekFrom::Start(seek)).map_err(io_error)?;
 
     // Allocate the buffer in the vm memory and read directly from the file into it
     let output = {
-        vm.allocate(args.1)?;
-        let r: MemoryRange = MemoryRange::new(vm.registers()[RegId::HP], args.1)?;
+        vm.allocate(len)?;
+        let r: MemoryRange = MemoryRange::new(vm.registers()[RegId::HP], len)?;
         let len = file
             .read(&mut vm.memory_mut()[r.usizes()])
-            .map_err(|_| PanicReason::EcalError)?;
+            .map_err(io_error)?;
 
         #[cfg(debug_assertions)]
         println!("read_file read {len} bytes");
@@ -62,16 +50,42 @@ fn read_file_ecal<S, Tx>(
     };
 
     let output_bytes: Vec<u8> =
-        fuels::core::codec::calldata!(output).expect("Failed to encode output tuple");
-    vm.allocate(output_bytes.len() as u64)?;
-    let o = MemoryRange::new(vm.registers()[RegId::HP], output_bytes.len())?;
-
-    #[cfg(debug_assertions)]
-    println!("output = {} {:?}", o.start, o.usizes());
-    vm.memory_mut()[o.usizes()].copy_from_slice(&output_bytes);
+        fuels::core::codec::calldata!(output).map_err(|e| EcalError::Encode {
+            type_id: None,
+            reason: e.to_string(),
+        })?;
 
     // Return the address of the output tuple through the rB register
-    vm.registers_mut()[rb] = o.start as u64;
+    super::write_output(vm, rb, &output_bytes)
+}
+
+// Resolve `path` relative to `root`. Only plain relative paths are accepted,
+// and the resolved file, after following symlinks, must still be under `root`.
+fn sandboxed_path(root: &Path, path: &str) -> Result<PathBuf, String> {
+    let relative = Path::new(path);
+    if relative.as_os_str().is_empty() {
+        return Err("empty path".to_string());
+    }
+    if !relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(format!(
+            "'{path}' must be relative and must not contain '..'"
+        ));
+    }
 
-    Ok(())
+    let root = root
+        .canonicalize()
+        .map_err(|e| format!("files root {root:?}: {e}"))?;
+    let resolved = root
+        .join(relative)
+        .canonicalize()
+        .map_err(|e| format!("'{path}': {e}"))?;
+    if !resolved.starts_with(&root) {
+        return Err(format!(
+            "'{path}' resolves to {resolved:?}, outside of {root:?}"
+        ));
+    }
+    Ok(resolved)
 }
@@ -1,95 +1,415 @@
+use fuel_abi_types::abi::program::{TypeApplication, TypeDeclaration};
 use fuel_asm::RegId;
-use fuel_vm::{
-    error::SimpleResult,
-    prelude::{Interpreter, MemoryRange},
-};
-use fuels::core::codec::ABIDecoder;
+use fuel_vm::prelude::Interpreter;
+use fuels::core::codec::{ABIDecoder, ABIEncoder};
+use fuels::types::param_types::ParamType;
 use fuels::types::Token;
+use sha2::{Digest, Sha256};
+use sqlx::{postgres::PgRow, Row};
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
+use super::write_buffer::MAX_ARGS;
+use super::{EcalError, EcalResult};
+use crate::db::{Db, SqlArg};
 use crate::extensions::*;
+use crate::sql::sql_table_builder::{
+    array_parent_column, array_table_name, column_option, fieldless_enum_type,
+    tuple_element_column, EntityKeys, PrimaryKeys, CONTENT_HASH_COLUMN, ENUM_VARIANT_COLUMN,
+};
 
-pub fn save<S, Tx>(vm: &mut Interpreter<S, Tx, super::MyEcal>, rb: RegId) -> SimpleResult<()> {
+pub fn save<S, Tx>(vm: &mut Interpreter<S, Tx, super::MyEcal>, rb: RegId) -> EcalResult<()> {
     let start = std::time::Instant::now();
 
     #[cfg(debug_assertions)]
     println!(">> ECAL::save()");
-    let (type_id, addr, size): (u64, u64, u64) = {
-        let addr = vm.registers()[rb];
-        let r = MemoryRange::new(addr, 3 * 8)?;
-        let bytes: [u8; 3 * 8] = vm.memory()[r.usizes()].try_into().unwrap();
-        fuels::core::codec::try_from_bytes(&bytes, super::DECODER_CONFIG).unwrap()
-    };
+    let [type_id, addr, size, return_id] = super::read_words(vm, rb)?;
     let type_id = type_id as usize;
 
-    let data = {
-        let r = MemoryRange::new(addr, size)?;
-        let mut bytes = Vec::with_capacity(size as usize);
-        bytes.extend_from_slice(&vm.memory()[r.usizes()]);
-        bytes
-    };
+    let data = super::read_memory(vm, addr, size)?;
+    let gas_costs = vm.ecal_state().gas_costs;
+    vm.gas_charge(gas_costs.decoding(size))?;
 
-    let param_type = vm.ecal_state_mut().abi.param_type(type_id);
+    let param_type = super::param_type(&vm.ecal_state().abi, type_id)?;
     let tokens = ABIDecoder::new(super::DECODER_CONFIG)
         .decode(&param_type, data.as_ref())
-        .unwrap();
+        .map_err(|e| EcalError::Decode {
+            type_id: Some(type_id),
+            reason: e.to_string(),
+        })?;
     // println!(">> SAVE_ANY_TOKENS\n{tokens:#?}");
     // let stmt = save_any(&vm.ecal_state().abi, type_id, tokens);
+    if let Some(buffer) = vm.ecal_state().write_buffer.clone() {
+        // A statement can't update a row twice, so an update of a keyed
+        // entity that is already in the buffer goes into the next one.
+        let updates_buffered = !vm.ecal_state().entity_keys.is_empty()
+            && buffer.updates_buffered(vm.ecal_state().save_stmt_builder(), type_id, &tokens)?;
+        if buffer.is_full() || updates_buffered {
+            buffer.flush(vm.ecal_state().db()?)?;
+        }
+        let (statements, rows, row) =
+            buffer.push(|| vm.ecal_state().save_stmt_builder(), type_id, tokens)?;
+        vm.gas_charge(insert_cost(&gas_costs, statements, rows))?;
+        // The id is only known once the row is written, so the script asking
+        // for it flushes the buffer.
+        let id = if return_id != 0 {
+            buffer.flush_returning(vm.ecal_state().db()?, row.as_ref())?
+        } else {
+            0
+        };
+        #[cfg(debug_assertions)]
+        println!(
+            "ECAL::save: {:?}, buffered {statements} statements, {rows} rows",
+            start.elapsed()
+        );
+        vm.registers_mut()[rb] = id;
+        return Ok(());
+    }
+
     let generate_start = std::time::Instant::now();
-    let stmt = SaveStmtBuilder::new(vm.ecal_state().abi.clone()).generate_stmt(type_id, tokens);
+    let mut builder = vm.ecal_state().save_stmt_builder();
+    let (stmt, row) = builder.generate_stmt(type_id, tokens)?;
     let generate_duration = generate_start.elapsed();
 
+    // Charge for the statements before running them.
+    vm.gas_charge(insert_cost(
+        &gas_costs,
+        builder.statements(),
+        builder.rows(),
+    ))?;
+
     #[cfg(debug_assertions)]
     println!(">> SAVE_STMT\n{stmt}");
 
     let exec_start = std::time::Instant::now();
-    let rows_affected =
-        futures::executor::block_on(sqlx::query(&stmt).execute(&vm.ecal_state().db_pool))
-            .unwrap()
-            .rows_affected();
+    let id =
+        fetch_id(vm.ecal_state().db()?, &stmt, &builder.args, row.as_ref()).map_err(|source| {
+            EcalError::Sql {
+                type_id,
+                sql: stmt.clone(),
+                source,
+            }
+        })?;
     let exec_duration = exec_start.elapsed();
 
     let duration = start.elapsed();
 
-    println!("ECAL::save: {duration:?}, stmt gen: {generate_duration:?}, stmt exec: {exec_duration:?}, rows affected {rows_affected}");
+    println!("ECAL::save: {duration:?}, stmt gen: {generate_duration:?}, stmt exec: {exec_duration:?}, id {id}");
 
+    // Return the id of the saved row through the rB register, 0 if the value
+    // has no row of its own.
+    vm.registers_mut()[rb] = id;
     Ok(())
 }
 
-// WITH
-//   MyStruct as (select one, two from mystruct),
-//   MyOtherStruct as (select (value) from myotherstruct) (select * from MyStruct, MyOtherStruct);
+// Saves the `count` values of type `type_id` at `addr`, `elt_size` bytes each,
+// e.g. the elements of a `Vec<T>`, with as few statements as possible. The
+// ids of their rows are returned in newly allocated memory, a word per value,
+// 0 for values without a row of their own.
+pub fn save_many<S, Tx>(vm: &mut Interpreter<S, Tx, super::MyEcal>, rb: RegId) -> EcalResult<()> {
+    #[cfg(debug_assertions)]
+    let start = std::time::Instant::now();
+
+    let [type_id, addr, count, elt_size] = super::read_words(vm, rb)?;
+    let type_id = type_id as usize;
+    #[cfg(debug_assertions)]
+    println!(">> ECAL::save_many(type_id={type_id}, count={count})");
+    if count > 0 && elt_size == 0 {
+        return Err(EcalError::Decode {
+            type_id: Some(type_id),
+            reason: "values of size 0".to_string(),
+        });
+    }
+
+    let size = count.saturating_mul(elt_size);
+    let data = super::read_memory(vm, addr, size)?;
+    let gas_costs = vm.ecal_state().gas_costs;
+    vm.gas_charge(gas_costs.decoding(size))?;
+
+    let param_type = super::param_type(&vm.ecal_state().abi, type_id)?;
+    let decoder = ABIDecoder::new(super::DECODER_CONFIG);
+    let values = data
+        .chunks_exact(elt_size.max(1) as usize)
+        .map(|chunk| {
+            decoder
+                .decode(&param_type, chunk)
+                .map_err(|e| EcalError::Decode {
+                    type_id: Some(type_id),
+                    reason: e.to_string(),
+                })
+        })
+        .collect::<EcalResult<Vec<_>>>()?;
+
+    // The ids are only known once the rows are written, so buffered values
+    // go first.
+    if let Some(buffer) = &vm.ecal_state().write_buffer {
+        buffer.flush(vm.ecal_state().db()?)?;
+    }
+
+    // A statement can't update a row twice, see `save`, nor take more than
+    // 65535 parameters. Such values go into the next statement.
+    let ecal = vm.ecal_state();
+    let mut batches: Vec<(SaveStmtBuilder, Vec<Option<RowRef>>)> = vec![];
+    for value in values {
+        let starts_batch = match batches.last() {
+            None => true,
+            Some((builder, _)) if builder.args.len() >= MAX_ARGS => true,
+            Some((builder, _)) if !ecal.entity_keys.is_empty() => {
+                let mut probe = ecal.save_stmt_builder();
+                probe.save_value(type_id, value.clone())?;
+                builder.conflicts_with(&probe)
+            }
+            Some(_) => false,
+        };
+        if starts_batch {
+            batches.push((ecal.save_stmt_builder(), vec![]));
+        }
+        let (builder, ids) = batches.last_mut().unwrap();
+        ids.push(builder.save_value(type_id, value)?);
+    }
 
-struct SaveStmtBuilder {
-    unique_stmts: HashSet<String>,
-    stmts: Vec<String>,
+    // Charge for the statements before running them.
+    let statements = batches
+        .iter()
+        .map(|(builder, _)| builder.statements())
+        .sum();
+    let rows = batches.iter().map(|(builder, _)| builder.rows()).sum();
+    vm.gas_charge(insert_cost(&gas_costs, statements, rows))?;
+
+    let mut ids = Vec::with_capacity(count as usize);
+    for (builder, batch_ids) in &batches {
+        let stmt = builder.stmt_many(batch_ids);
+        #[cfg(debug_assertions)]
+        println!(">> SAVE_MANY_STMT\n{stmt}");
+        let batch_ids = fetch_ids(vm.ecal_state().db()?, &stmt, &builder.args, batch_ids.len())
+            .map_err(|source| EcalError::Sql {
+                type_id,
+                sql: stmt.clone(),
+                source,
+            })?;
+        ids.extend(batch_ids);
+    }
+
+    #[cfg(debug_assertions)]
+    println!(
+        "ECAL::save_many: {:?}, {count} values, {} statements",
+        start.elapsed(),
+        batches.len()
+    );
+
+    let bytes: Vec<u8> = ids.iter().flat_map(|id| id.to_be_bytes()).collect();
+    // Return the address of the ids through the rB register
+    super::write_output(vm, rb, &bytes)
+}
+
+// Charged before the statements run, as with a write buffer they may only
+// run after the VM is done. See `SaveStmtBuilder::statements` and
+// `SaveStmtBuilder::rows`.
+fn insert_cost(gas_costs: &super::GasCosts, stmts: usize, rows: usize) -> u64 {
+    gas_costs
+        .statements(stmts as u64)
+        .saturating_add(gas_costs.rows(rows as u64))
+}
+
+// Runs a statement from `SaveStmtBuilder::stmt` and returns the id it
+// selects, 0 if it selects none. `id` is the `id` passed to `stmt`.
+pub(super) fn fetch_id(
+    db: &Db,
+    stmt: &str,
+    args: &[SqlArg],
+    id: Option<&RowRef>,
+) -> Result<u64, sqlx::Error> {
+    let rows = db.fetch_all(stmt, args)?;
+    match rows.first() {
+        Some(row) => Ok(row.try_get::<i64, _>("id")? as u64),
+        None if id.is_none() => Ok(0),
+        // Every row inserted is returned, see `TableRows`.
+        None => Err(sqlx::Error::RowNotFound),
+    }
+}
+
+// Like `fetch_id`, for a statement from `SaveStmtBuilder::stmt_many`
+// selecting `n` ids.
+pub(super) fn fetch_ids(
+    db: &Db,
+    stmt: &str,
+    args: &[SqlArg],
+    n: usize,
+) -> Result<Vec<u64>, sqlx::Error> {
+    let rows = db.fetch_all(stmt, args)?;
+    if rows.len() != n {
+        return Err(sqlx::Error::RowNotFound);
+    }
+    rows.iter()
+        .map(|row| Ok(row.try_get::<i64, _>("id")? as u64))
+        .collect()
+}
+
+// Generates one statement saving any number of values, with a CTE per table
+// inserting all of its rows, and one per array table linking the elements:
+//
+// WITH
+//   "P_rows" AS (INSERT INTO "P" (content_hash, p) VALUES ($1, $2), ($3, $4)
+//     ON CONFLICT (content_hash) DO UPDATE ... RETURNING id, content_hash),
+//   "Q_rows" AS (INSERT INTO "Q" (content_hash, "p_1Id", "p_2Id") VALUES ($5,
+//     (SELECT id FROM "P_rows" WHERE content_hash = $1),
+//     (SELECT id FROM "P_rows" WHERE content_hash = $3)) ...)
+// SELECT ...
+//
+// Rows refer to other rows by content hash, so the tables they refer to go
+// first.
+#[derive(Debug)]
+pub(super) struct SaveStmtBuilder {
+    // The rows of each table, in the order the tables were first saved to.
+    tables: Vec<TableRows>,
+    // The links of each array table, see `LinkRows`.
+    links: Vec<LinkRows>,
+    // The links removed from updated values, see `Unlinks`.
+    unlinks: Vec<Unlinks>,
+    // Bind parameters of the statements, `args[n - 1]` for `$n`.
+    pub(super) args: Vec<SqlArg>,
+    arg_numbers: HashMap<SqlArg, usize>,
+    // The keyed entities saved so far, by type and key, with their content
+    // hashes.
+    updates: HashMap<(String, SqlArg), [u8; 32]>,
+    primary_keys: PrimaryKeys,
+    entity_keys: EntityKeys,
     abi: crate::ABI,
 }
 
 impl SaveStmtBuilder {
-    pub fn new(abi: crate::ABI) -> Self {
+    pub fn new(abi: crate::ABI, primary_keys: PrimaryKeys, entity_keys: EntityKeys) -> Self {
         Self {
             abi,
-            stmts: vec![],
-            unique_stmts: HashSet::new(),
+            primary_keys,
+            entity_keys,
+            tables: vec![],
+            links: vec![],
+            unlinks: vec![],
+            args: vec![],
+            arg_numbers: HashMap::new(),
+            updates: HashMap::new(),
         }
     }
 
-    pub fn generate_stmt(&mut self, type_id: usize, target_value: Token) -> String {
+    // Whether `other` saves a keyed entity that this builder saves with a
+    // different value.
+    pub(super) fn conflicts_with(&self, other: &SaveStmtBuilder) -> bool {
+        other
+            .updates
+            .iter()
+            .any(|(key, hash)| self.updates.get(key).is_some_and(|h| h != hash))
+    }
+
+    // The number of CTEs of the statement.
+    pub(super) fn statements(&self) -> usize {
+        self.tables.len() + self.links.len() + self.unlinks.len()
+    }
+
+    // The number of rows the statement inserts or updates, and of deletes.
+    pub(super) fn rows(&self) -> usize {
+        let rows: usize = self.tables.iter().map(|table| table.rows.len()).sum();
+        let links: usize = self.links.iter().map(|links| links.rows.len()).sum();
+        let unlinks: usize = self
+            .unlinks
+            .iter()
+            .map(|unlinks| unlinks.parents.len())
+            .sum();
+        rows + links + unlinks
+    }
+
+    // Returns the statement, and the row whose id it selects.
+    pub fn generate_stmt(
+        &mut self,
+        type_id: usize,
+        target_value: Token,
+    ) -> EcalResult<(String, Option<RowRef>)> {
         #[cfg(debug_assertions)]
         println!("GENERATE_STMT {type_id} {target_value:#?}");
-        self.save_value(type_id, target_value);
-        let stmts = self.stmts.join(", ");
-        // This will not return any rows, but it's a valid SQL statement.
-        let noop = "SELECT 1 WHERE FALSE";
-        // A valid SQL statement must follow the WITH clause
-        format!("WITH {stmts} {noop}")
+        let id = self.save_value(type_id, target_value)?;
+        Ok((self.stmt(id.as_ref()), id))
+    }
+
+    // One statement saving everything passed to `save_value` so far, and
+    // selecting the id of the `id` row, if any.
+    pub fn stmt(&self, id: Option<&RowRef>) -> String {
+        match id {
+            // Serial ids are INTEGER, content ids BIGINT.
+            Some(id) => self.with(format!("SELECT {}::bigint AS id", id.id())),
+            // This will not return any rows, but it's a valid SQL statement.
+            None => self.with("SELECT 1 WHERE FALSE".to_string()),
+        }
+    }
+
+    // One statement saving everything passed to `save_value` so far, and
+    // selecting the ids of the `ids` rows, in order, 0 for `None`.
+    pub(super) fn stmt_many(&self, ids: &[Option<RowRef>]) -> String {
+        let ids: Vec<String> = ids
+            .iter()
+            .enumerate()
+            .map(|(n, id)| {
+                let id = id.as_ref().map_or("0".to_string(), RowRef::id);
+                format!("({n}, {id}::bigint)")
+            })
+            .collect();
+        self.with(format!(
+            "SELECT id FROM (VALUES {}) AS ids(n, id) ORDER BY n",
+            ids.join(", ")
+        ))
+    }
+
+    // `select` preceded by the CTEs saving the rows.
+    fn with(&self, select: String) -> String {
+        let mut ctes: Vec<String> = self
+            .ordered_tables()
+            .into_iter()
+            .map(TableRows::insert)
+            .collect();
+        ctes.extend(self.unlinks.iter().map(Unlinks::delete));
+        ctes.extend(self.links.iter().map(LinkRows::insert));
+        if ctes.is_empty() {
+            select
+        } else {
+            format!("WITH {} {select}", ctes.join(", "))
+        }
+    }
+
+    // The tables, each after the tables its rows refer to. Types can't
+    // contain themselves, so there are no cycles.
+    fn ordered_tables(&self) -> Vec<&TableRows> {
+        fn visit<'a>(
+            tables: &'a [TableRows],
+            n: usize,
+            visited: &mut HashSet<usize>,
+            order: &mut Vec<&'a TableRows>,
+        ) {
+            if !visited.insert(n) {
+                return;
+            }
+            for table in &tables[n].references {
+                if let Some(m) = tables.iter().position(|rows| rows.table == *table) {
+                    visit(tables, m, visited, order);
+                }
+            }
+            order.push(&tables[n]);
+        }
+        let mut visited = HashSet::new();
+        let mut order = vec![];
+        for n in 0..self.tables.len() {
+            visit(&self.tables, n, &mut visited, &mut order);
+        }
+        order
     }
 
-    fn save_value(&mut self, type_id: usize, target_value: Token) {
+    // Returns the saved row, if the value has a row of its own, i.e. it's not
+    // an array, `None` or `()`.
+    pub(super) fn save_value(
+        &mut self,
+        type_id: usize,
+        target_value: Token,
+    ) -> EcalResult<Option<RowRef>> {
         if target_value == Token::Unit {
-            return;
+            return Ok(None);
         };
 
         let target_decl = self.abi.type_declaration(type_id);
@@ -101,7 +421,25 @@ impl SaveStmtBuilder {
         );
 
         if !(target_value.is_array() || target_value.is_enum() || target_value.is_struct()) {
-            panic!("Expected array, enum, or struct, but got: {target_value:?}");
+            return Err(EcalError::Unsupported {
+                type_id,
+                reason: format!(
+                    "saving '{}', only arrays, enums and structs have rows",
+                    target_decl.type_field
+                ),
+            });
+        }
+
+        // Stored in the column of the field holding it, there's no row to
+        // point to.
+        if target_decl.is_fieldless_enum(&self.abi) {
+            return Err(EcalError::Unsupported {
+                type_id,
+                reason: format!(
+                    "saving '{}' on its own, fieldless enums are stored in the columns of fields",
+                    target_decl.type_field
+                ),
+            });
         }
 
         if target_decl.is_option() {
@@ -114,9 +452,9 @@ impl SaveStmtBuilder {
             println!(">> SAVING ELT type={elt_type:#?} elt={elt:?}");
             // If `Some(v)`, save `v`, else, do nothing when `None`
             if !(target_decl.type_field == "()") {
-                self.save_value(elt_type.type_id, elt.clone());
+                return self.save_value(elt_type.type_id, elt.clone());
             }
-            return;
+            return Ok(None);
         }
 
         if target_decl.is_array() {
@@ -127,9 +465,9 @@ impl SaveStmtBuilder {
                 #[cfg(debug_assertions)]
                 println!(">> SAVING ELT type={elt_type:#?} elt={elt:?}");
                 let type_id = elt_type.type_arguments.as_ref().unwrap()[0].type_id;
-                self.save_value(type_id, elt.clone())
+                self.save_value(type_id, elt.clone())?;
             }
-            return;
+            return Ok(None);
         }
 
         let toks = if target_decl.is_struct() {
@@ -145,7 +483,10 @@ impl SaveStmtBuilder {
         } else if target_decl.type_field == "()" {
             vec![]
         } else {
-            panic!("{target_decl:#?}\n{target_value:#?}")
+            return Err(EcalError::Unsupported {
+                type_id,
+                reason: format!("saving '{}'", target_decl.type_field),
+            });
         };
 
         let mut columns: Vec<String> = target_decl
@@ -153,31 +494,25 @@ impl SaveStmtBuilder {
             .as_ref()
             .unwrap()
             .iter()
-            .filter_map(|field| {
-                let decl = self.abi.type_declaration(field.type_id);
-                if decl.is_array() {
-                    None
-                } else if (decl.is_struct() || decl.is_enum()) && !decl.is_u256() {
-                    Some(format!("\"{}Id\"", field.name))
-                } else {
-                    Some(format!("\"{}\"", field.name))
-                }
-            })
+            .flat_map(|field| field_columns(&self.abi, &field.name, field))
             .collect();
-        if target_decl.has_nested_struct(&self.abi)
+        // Enums take the columns of their active variant, so they always
+        // take this branch, see `SQLTableBuilder::process_enum`.
+        if target_decl.is_enum()
+            || target_decl.has_nested_struct(&self.abi)
             || target_decl.has_nested_enum(&self.abi)
             || target_decl.has_nested_array(&self.abi)
         {
             let mut selects: Vec<String> = vec![];
-            let mut sources: Vec<String> = vec![];
-            let mut wheres = vec![];
+            // The tables of the rows the values refer to.
+            let mut references: Vec<String> = vec![];
+            // Array fields, with the positions and rows of their elements.
+            let mut arrays: Vec<(String, Vec<(usize, RowRef)>)> = vec![];
             let inner_types = if target_decl.is_enum() {
                 let n = target_value.as_enum().0;
                 let k = target_decl.components.as_ref().unwrap()[n as usize].clone();
-                // An enum is like a one-element Array, or a one-field struct.
+                // An enum is like a one-field struct.
                 vec![k]
-            } else if target_decl.is_array() {
-                panic!("")
             } else {
                 target_decl.components.as_ref().unwrap().clone()
             };
@@ -190,11 +525,6 @@ impl SaveStmtBuilder {
                     i = i + 1,
                     name = field.name
                 );
-                let field_name = if field_decl.is_struct() || field_decl.is_enum() {
-                    format!("{}Id", field.name)
-                } else {
-                    field.name.clone()
-                };
 
                 //
                 // UNIT
@@ -203,10 +533,14 @@ impl SaveStmtBuilder {
                     #[cfg(debug_assertions)]
                     println!("UNIT SKIP")
                 //
-                // U256
+                // U128, U256
                 //
-                } else if field_decl.is_u256() {
-                    selects.push(tok_to_string(&toks[i]));
+                } else if field_decl.is_std_scalar() {
+                    let param_type = self.abi.param_type(field.type_id);
+                    for arg in scalar_args(field.type_id, &param_type, &toks[i])? {
+                        let arg = self.bind(arg);
+                        selects.push(arg);
+                    }
                 //
                 // ARRAY
                 //
@@ -220,326 +554,750 @@ impl SaveStmtBuilder {
                     println!("ARR TYPE: {arr_type:#?}\nARR ELT DECL:\n{arr_elt_decl:#?}");
 
                     let elt_type = arr_type.type_arguments.as_ref().unwrap()[0].clone();
-                    let elt_decl = self.abi.types.get(&elt_type.type_id).unwrap();
 
                     #[cfg(debug_assertions)]
-                    println!("ELT TYPE: {elt_type:#?}\n{elt_decl:#?}");
+                    println!(
+                        "ELT TYPE: {elt_type:#?}\n{:#?}",
+                        self.abi.types.get(&elt_type.type_id).unwrap()
+                    );
 
                     // [Option<u8>; N] to simulate Vec<u8>
-                    if arr_elt_decl.type_field == "enum Option" && !elt_decl.is_entity() {
-                        // TODO: turn [Option<u8>; N] into [u8] and save it as hex string
+                    if field_decl.is_byte_array(&self.abi) {
+                        let bytes = self.bind(SqlArg::Bytes(some_prefix(t)));
+                        selects.push(bytes);
                     } else {
-                        for elt in t.as_array() {
-                            self.save_value(elt_type.type_id, elt.as_enum().1)
+                        let mut elts = vec![];
+                        for (position, elt) in t.as_array().iter().enumerate() {
+                            if let Some(id) = self.save_value(elt_type.type_id, elt.as_enum().1)? {
+                                elts.push((position, id));
+                            }
+                        }
+                        // Arrays have foreign keys pointing back, so the
+                        // rows linking the elements to this value are
+                        // inserted after it, see below.
+                        if target_decl.is_struct() && arr_elt_decl.is_option() {
+                            arrays.push((field.name.clone(), elts));
                         }
                     }
-
-                    // Arrays have foreign keys pointing back.
-
-                    // TODO: For example Block { transactions:
-                    // [Option<Transaction>; 7]} After we've saved the elements
-                    // of the array, we need to get the ID's and add an entry to
-                    // the Trasnactions column
                     continue;
                 //
-                // ENUM
+                // FIELDLESS ENUM
                 //
-                } else if field_decl.is_enum() {
-                    let field_struct_name = field_decl.struct_or_enum_name().unwrap();
-
-                    #[cfg(debug_assertions)]
-                    println!(
-                        "BLARG OUTER:{target_decl:#?}\nFIELD {name} {field_decl:#?}",
-                        name = field.name
-                    );
-                    if target_decl.is_array() {
-                        #[cfg(debug_assertions)]
-                        println!("ARRAY SKIP");
-                    } else {
-                        self.save_value(
-                            field_decl.type_id,
-                            if field_decl.is_struct() {
-                                #[cfg(debug_assertions)]
-                                println!("ONE");
-                                toks[i].clone()
-                            } else if field_decl.is_enum() {
-                                #[cfg(debug_assertions)]
-                                println!("TWO {}", toks[i].clone());
-                                toks[i].clone()
-                            } else {
-                                panic!("BLARG")
-                            },
-                        );
-                    }
-
-                    let field_struct_hash = hash_tokens(&vec![toks[i].as_enum().1]);
-
-                    for variant in target_decl.components.as_ref().unwrap() {
-                        let variant_decl = self.abi.types.get(&variant.type_id).unwrap();
-                        if variant_decl.is_array() {
-                            continue;
+                } else if field_decl.is_fieldless_enum(&self.abi) {
+                    let arg = self.bind_variant(&field_decl, &toks[i]);
+                    selects.push(arg);
+                //
+                // OPTION IN A COLUMN
+                //
+                } else if let Some(some) = column_option(&self.abi, field) {
+                    let some_decl = self.abi.type_declaration(some.type_id);
+                    match toks[i].as_enum() {
+                        (1, value, _) if some_decl.is_fieldless_enum(&self.abi) => {
+                            let arg = self.bind_variant(&some_decl, &value);
+                            selects.push(arg);
                         }
-                        #[cfg(debug_assertions)]
-                        println!("VARIANT:\n{variant:#?}");
-                        if target_decl.is_enum() && variant.name != field.name {
-                            // NULLs for the values of other variants
-                            selects.push(format!("NULL as {}Id", variant.name));
-                            #[cfg(debug_assertions)]
-                            println!("SELECTS 5 {:?}", selects.last());
-                        } else if target_decl.is_struct() {
-                            // Id for the value of active variant
-                            selects.push(format!(
-                                "{field_struct_name}_id_{field_struct_hash}.id AS {field_name}"
-                            ));
-                            #[cfg(debug_assertions)]
-                            println!(
-                                "SELECTS 4 {:?}\n{target_decl:#?}\n{variant_decl:#?}",
-                                selects.last()
-                            );
+                        // The type of `Some` is concrete in the token.
+                        (1, value, variants) => {
+                            let some_type = &variants.param_types()[1];
+                            for arg in scalar_args(field.type_id, some_type, &value)? {
+                                let arg = self.bind(arg);
+                                selects.push(arg);
+                            }
                         }
+                        _ => selects.push("NULL".to_string()),
                     }
-
-                    let source = format!("{field_struct_name}_id_{field_struct_hash}");
-
-                    if !sources.contains(&source) {
-                        sources.push(format!("{field_struct_name}_id_{field_struct_hash}"));
-                    }
-
-                    wheres.push(format!(
-                            "\"{field_name}\" = (SELECT id FROM {field_struct_name}_id_{field_struct_hash})"
-                        ));
                 //
-                // STRUCT
+                // STRUCT, ENUM, OPTION
                 //
-                } else if field_decl.is_struct() {
-                    let field_struct_name = field_decl.struct_or_enum_name().unwrap();
-                    self.save_value(
-                        field_decl.type_id,
-                        if field_decl.is_struct() {
-                            toks[i].clone()
-                        } else {
-                            toks[0].clone()
-                        },
-                    );
-
-                    let field_struct_hash = hash_tokens(&toks[i].as_struct());
-
-                    if target_decl.is_struct() {
-                        selects.push(format!(
-                            "{field_struct_name}_id_{field_struct_hash}.id AS {field_name}"
-                        ));
-                        #[cfg(debug_assertions)]
-                        println!("SELECTS 1 {:?}", selects.last());
-                    } else if target_decl.is_enum() {
-                        for variant in target_decl.components.as_ref().unwrap() {
-                            #[cfg(debug_assertions)]
-                            println!("VARIANT 2:\n{variant:#?}");
-                            if target_decl.is_enum() && variant.name != field.name {
-                                // NULLs for the values of other variants
-                                selects.push(format!("NULL as {}Id", variant.name));
-                                #[cfg(debug_assertions)]
-                                println!("SELECTS 2 {:?}", selects.last());
-                            } else {
-                                // Id for the value of active variant
-                                #[cfg(debug_assertions)]
-                                println!("SELECTS 3");
-
-                                selects.push(format!(
-                                    "{field_struct_name}_id_{field_struct_hash}.id AS {field_name}"
-                                ));
-
-                                #[cfg(debug_assertions)]
-                                println!("SELECTS 3 {:?}", selects.last());
-                            }
+                } else if field_decl.is_struct() || field_decl.is_enum() {
+                    match self.save_field(field, toks[i].clone())? {
+                        Some(row) => {
+                            selects.push(row.id());
+                            references.push(row.table);
                         }
+                        // `None`
+                        None => selects.push("NULL".to_string()),
                     }
-
-                    let source = format!("{field_struct_name}_id_{field_struct_hash}");
-
-                    if !sources.contains(&source) {
-                        sources.push(format!("{field_struct_name}_id_{field_struct_hash}"));
-                    }
-
-                    wheres.push(format!(
-                            "\"{field_name}\" = (SELECT id FROM {field_struct_name}_id_{field_struct_hash})"
-                        ));
                 //
                 // OTHER
                 //
                 } else {
-                    let tok = toks[i].clone();
-                    if tok.is_array() {
-                        // let elt_type = target_decl.components.as_ref().unwrap().clone()[0].clone();
-                        // for elt in tok.as_array() {
-                        //     self.save_value(field_decl.type_id, elt.clone())
-                        // }
-                    } else {
-                        selects.push(tok_to_string(&tok));
-                        wheres.push(format!("\"{field_name}\" = {}", tok_to_string(&tok)));
+                    let param_type = self.abi.param_type(field.type_id);
+                    for arg in scalar_args(field.type_id, &param_type, &toks[i])? {
+                        let arg = self.bind(arg);
+                        selects.push(arg);
                     }
                 }
             }
-            let selects = selects.join(", ");
-            let sources = sources.join(", ");
-            let wheres = wheres.join(" AND ");
-            let hash = hash_tokens(&toks);
-
             let struct_name = target_decl.struct_or_enum_name().unwrap();
-            let columns = if columns.is_empty() {
-                "".to_string()
-            } else {
-                format!("({})", columns.join(", "))
-            };
-            let wheres = if wheres.is_empty() {
-                "".to_string()
-            } else {
-                "WHERE ".to_string() + &wheres
-            };
-            let sources = if sources.is_empty() {
-                "".to_string()
-            } else {
-                "FROM ".to_string() + &sources
-            };
-            let stmt = format!("{struct_name}_new_row_{hash} AS (INSERT INTO \"{struct_name}\" {columns} (SELECT {selects} {sources} WHERE NOT EXISTS (SELECT 1 FROM \"{struct_name}\" {wheres})) RETURNING id)");
-            self.push_stmt(stmt);
+            if target_decl.is_enum() {
+                let variant = &inner_types[0];
+                columns = field_columns(&self.abi, &variant.name, variant);
+                columns.insert(0, format!("\"{ENUM_VARIANT_COLUMN}\""));
+                selects.insert(0, self.bind(SqlArg::Text(variant.name.clone())));
+            }
+            let key = self.row_key(&target_decl, &target_value)?;
+            let updated = key.upsert.is_some();
+            let row = self.push_row(&struct_name, key, columns, selects, references);
 
-            let stmt = format!("{struct_name}_id_{hash} AS (SELECT id from {struct_name}_new_row_{hash} UNION ALL SELECT id from \"{struct_name}\" {wheres} LIMIT 1)");
-            self.push_stmt(stmt);
-        // No nested struct, enum, or array
+            for (field_name, elts) in arrays {
+                let table = array_table_name(&field_name);
+                let parent_column = array_parent_column(&struct_name);
+                // An updated value gets the elements of its new value: the
+                // links at its positions are updated, the others deleted.
+                if updated {
+                    let positions = elts.iter().map(|(position, _)| *position).collect();
+                    self.push_unlink(&table, &parent_column, &row, positions);
+                }
+                for (position, elt) in elts {
+                    self.push_link(&table, &parent_column, &row, position, elt);
+                }
+            }
+
+            Ok(Some(row))
+        // A struct without nested structs, enums, or arrays
         } else {
             #[cfg(debug_assertions)]
             println!("PLAIN DATA");
-            let mut where_clause = vec![];
             let mut values: Vec<String> = vec![];
-            for (i, t) in toks.iter().enumerate() {
-                // TODO: plain enum without nested structs, enums, or arrays.
-                // HOW TO REPRESENT IT IN THE DB? CUSTOM ENUM TYPE?
-                if target_decl.is_enum() {
-                    let (n, b, c) = target_value.as_enum();
-
-                    columns = target_decl
-                        .components
-                        .as_ref()
-                        .unwrap()
-                        .iter()
-                        .enumerate()
-                        .filter_map(|(m, _)| {
-                            if n == (m as u64) {
-                                Some("Variant".to_string())
-                            } else {
-                                None
-                            }
-                        })
-                        .collect();
-                    // let mut columns: Vec<String> = target_decl
-                    //     .components
-                    //     .as_ref()
-                    //     .unwrap()
-                    //     .iter()
-                    //     .filter_map(|field| {
-                    //         let decl = self.abi.type_declaration(field.type_id);
-                    //         if decl.is_array() {
-                    //             None
-                    //         } else if (decl.is_struct() || decl.is_enum()) && !decl.is_u256() {
-                    //             Some(format!("\"{}Id\"", field.name))
-                    //         } else {
-                    //             Some(format!("\"{}\"", field.name))
-                    //         }
-                    //     })
-                    //     .collect();
-
-                    // TODO: not quite correct
-                    // columns[i] = format!("{}Id", columns[i]);
-
-                    // where_clause.push(format!("\"{}Variant\" = {}", col, n));
-                    where_clause.push(format!("Variant = {}", columns[i]));
-                    //values.push("3".to_string());
-                    //values.push(n.to_string());
-                } else {
-                    #[cfg(debug_assertions)]
-                    println!(
-                        "FOOBAR {} {t:?}",
-                        target_decl.struct_or_enum_name().unwrap()
-                    );
-                    where_clause.push(format!("{} = {}", columns[i], tok_to_string(t)));
-
-                    values.push(tok_to_string(t));
+            let fields = target_decl.components.clone().unwrap_or_default();
+            for (field, t) in fields.iter().zip(&toks) {
+                let param_type = self.abi.param_type(field.type_id);
+                for arg in scalar_args(field.type_id, &param_type, t)? {
+                    let arg = self.bind(arg);
+                    values.push(arg);
                 }
             }
-            let where_clause = where_clause.join(" AND ");
-
-            let hash = hash_tokens(&toks);
-
             let struct_name = target_decl.struct_or_enum_name().unwrap();
+            let key = self.row_key(&target_decl, &target_value)?;
+            let row = self.push_row(&struct_name, key, columns, values, vec![]);
 
-            let columns = if columns.is_empty() {
-                "".to_string()
-            } else {
-                format!("({})", columns.join(", "))
-            };
-            let where_clause = if where_clause.is_empty() {
-                "".to_string()
-            } else {
-                "WHERE ".to_string() + &where_clause
-            };
-            let values = if values.is_empty() {
-                "SELECT".to_string()
-            } else {
-                "SELECT ".to_string() + &values.join(", ")
+            Ok(Some(row))
+        }
+    }
+
+    // Saves the value of a field, or of an enum variant. Returns its row,
+    // `None` for `None`.
+    fn save_field(&mut self, field: &TypeApplication, value: Token) -> EcalResult<Option<RowRef>> {
+        let decl = self.abi.type_declaration(field.type_id);
+        if decl.is_option() {
+            // The type argument is the concrete type of `Some`.
+            return match value.as_enum() {
+                (1, value, _) => {
+                    let type_id = field.type_arguments.as_ref().unwrap()[0].type_id;
+                    self.save_value(type_id, value)
+                }
+                _ => Ok(None),
             };
-            let stmt = format!("{struct_name}_new_row_{hash} AS (INSERT INTO \"{struct_name}\" {columns} {values} WHERE NOT EXISTS (SELECT 1 FROM \"{struct_name}\" {where_clause}) RETURNING id)");
-            self.push_stmt(stmt);
+        }
+        self.save_value(field.type_id, value)
+    }
+
+    // Adds a row to the insert into `table`, unless a row with the same
+    // content hash is in it already, see `TableRows`. `values` are the values
+    // of `columns`, and `references` the tables of the rows they refer to.
+    fn push_row(
+        &mut self,
+        table: &str,
+        key: RowKey,
+        mut columns: Vec<String>,
+        mut values: Vec<String>,
+        references: Vec<String>,
+    ) -> RowRef {
+        columns.insert(0, CONTENT_HASH_COLUMN.to_string());
+        values.insert(0, key.hash.clone());
+        if let Some(id) = &key.id {
+            columns.insert(0, "id".to_string());
+            values.insert(0, id.clone());
+        }
+        let n = match self.tables.iter().position(|rows| rows.table == table) {
+            Some(n) => n,
+            None => {
+                self.tables.push(TableRows {
+                    table: table.to_string(),
+                    upsert: key.upsert,
+                    columns: vec![],
+                    rows: vec![],
+                    hashes: HashSet::new(),
+                    references: vec![],
+                });
+                self.tables.len() - 1
+            }
+        };
+        self.tables[n].push(&key.hash, columns, values, references);
+        RowRef {
+            table: table.to_string(),
+            hash: key.hash,
+        }
+    }
+
+    // Links `elt` to position `position` of the array of `parent` stored in
+    // `table`, see `LinkRows`.
+    fn push_link(
+        &mut self,
+        table: &str,
+        parent_column: &str,
+        parent: &RowRef,
+        position: usize,
+        elt: RowRef,
+    ) {
+        let n = match self
+            .links
+            .iter()
+            .position(|links| links.table == table && links.parent_column == parent_column)
+        {
+            Some(n) => n,
+            None => {
+                self.links.push(LinkRows {
+                    table: table.to_string(),
+                    parent_column: parent_column.to_string(),
+                    rows: vec![],
+                    keys: HashSet::new(),
+                });
+                self.links.len() - 1
+            }
+        };
+        let links = &mut self.links[n];
+        if links.keys.insert((parent.hash.clone(), position)) {
+            links.rows.push((parent.clone(), position, elt));
+        }
+    }
 
-            let stmt = format!("{struct_name}_id_{hash} AS (SELECT id from {struct_name}_new_row_{hash} UNION ALL SELECT id from \"{struct_name}\" {where_clause} LIMIT 1)");
-            self.push_stmt(stmt);
+    // Deletes the links of the array of `parent` stored in `table` at other
+    // positions than `positions`, see `Unlinks`.
+    fn push_unlink(
+        &mut self,
+        table: &str,
+        parent_column: &str,
+        parent: &RowRef,
+        positions: Vec<usize>,
+    ) {
+        let n =
+            match self.unlinks.iter().position(|unlinks| {
+                unlinks.table == table && unlinks.parent_column == parent_column
+            }) {
+                Some(n) => n,
+                None => {
+                    self.unlinks.push(Unlinks {
+                        table: table.to_string(),
+                        parent_column: parent_column.to_string(),
+                        parents: vec![],
+                    });
+                    self.unlinks.len() - 1
+                }
+            };
+        let unlinks = &mut self.unlinks[n];
+        if !unlinks.parents.iter().any(|(row, _)| row == parent) {
+            unlinks.parents.push((parent.clone(), positions));
         }
     }
 
-    fn push_stmt(&mut self, s: String) {
-        if self.unique_stmts.insert(s.clone()) {
-            self.stmts.push(s);
+    fn row_key(&mut self, decl: &TypeDeclaration, value: &Token) -> EcalResult<RowKey> {
+        let hash = content_hash(decl, value)?;
+        let (id_hash, upsert) = match self.key_field(decl) {
+            Some(key_field) => {
+                let (i, field) = decl
+                    .components
+                    .as_ref()
+                    .unwrap()
+                    .iter()
+                    .enumerate()
+                    .find(|(_, field)| field.name == key_field)
+                    .ok_or_else(|| EcalError::Unsupported {
+                        type_id: decl.type_id,
+                        reason: format!(
+                            "'{}' has no key field '{key_field}', see INDEXER_ENTITY_KEYS",
+                            decl.type_field
+                        ),
+                    })?;
+                let key = &value.as_struct()[i];
+                let key_arg = tok_to_arg(field.type_id, key)?;
+                self.updates
+                    .insert((decl.type_field.clone(), key_arg), hash);
+                // The id is the hash of the key, so it doesn't change when
+                // the value does.
+                (content_hash(decl, key)?, Some(format!("\"{key_field}\"")))
+            }
+            None => (hash, None),
+        };
+        let id = match self.primary_keys {
+            PrimaryKeys::Serial => None,
+            PrimaryKeys::ContentHash => Some(self.bind(SqlArg::Int(content_id(id_hash)))),
+        };
+        Ok(RowKey {
+            hash: self.bind(SqlArg::Bytes(hash.to_vec())),
+            id,
+            upsert,
+        })
+    }
+
+    fn key_field(&self, decl: &TypeDeclaration) -> Option<String> {
+        if !decl.is_struct() {
+            return None;
         }
+        let struct_name = decl.struct_or_enum_name()?;
+        self.entity_keys.get(&struct_name).map(str::to_string)
+    }
+
+    // The variant of a fieldless enum, cast to its Postgres ENUM type.
+    fn bind_variant(&mut self, decl: &TypeDeclaration, tok: &Token) -> String {
+        let arg = self.bind(SqlArg::Text(variant_name(decl, tok)));
+        let enum_type = fieldless_enum_type(&decl.struct_or_enum_name().unwrap());
+        format!("{arg}::{enum_type}")
+    }
+
+    // Returns the placeholder for `arg`. Equal arguments share a placeholder,
+    // so rows with equal content hashes are saved once, see `push_row`.
+    fn bind(&mut self, arg: SqlArg) -> String {
+        let n = match self.arg_numbers.get(&arg) {
+            Some(n) => *n,
+            None => {
+                self.args.push(arg.clone());
+                self.arg_numbers.insert(arg.clone(), self.args.len());
+                self.args.len()
+            }
+        };
+        arg.placeholder(n)
     }
 }
 
-fn tok_to_string(tok: &Token) -> String {
-    match tok {
-        Token::U8(x) => format!("{x}"),
-        Token::U16(x) => format!("{x}"),
-        Token::U32(x) => format!("{x}"),
-        Token::U64(x) => format!("{x}"),
-        Token::Bool(b) => format!("{b}"),
-        Token::B256(bytes) => format!("\'{}\'", hex::encode(bytes)),
+// Scalars are passed to the database as bind parameters, see
+// `SaveStmtBuilder::bind`. The column types are set by `SQLTableBuilder`.
+// `type_id` is the type of the field holding the value, for errors.
+pub(super) fn tok_to_arg(type_id: usize, tok: &Token) -> EcalResult<SqlArg> {
+    Ok(match tok {
+        Token::U8(x) => SqlArg::Int(*x as i64),
+        Token::U16(x) => SqlArg::Int(*x as i64),
+        Token::U32(x) => SqlArg::Int(*x as i64),
+        Token::U64(x) => SqlArg::Numeric(x.to_string()),
+        Token::Bool(b) => SqlArg::Bool(*b),
+        // hex-encoded
+        Token::U128(x) => SqlArg::Text(hex::encode(x.to_be_bytes())),
+        Token::B256(bytes) => SqlArg::Text(hex::encode(bytes)),
         Token::U256(value) => {
             let x = Into::<[u8; 32]>::into(*value);
-            format!("\'{}\'", hex::encode(x))
+            SqlArg::Text(hex::encode(x))
+        }
+        Token::String(s) => SqlArg::Text(s.clone()),
+        Token::StringArray(s) | Token::StringSlice(s) => SqlArg::Text(
+            s.get_encodable_str()
+                .map_err(|e| EcalError::Encode {
+                    type_id: Some(type_id),
+                    reason: e.to_string(),
+                })?
+                .to_string(),
+        ),
+        Token::Bytes(bytes) => SqlArg::Bytes(bytes.clone()),
+        // The words, big-endian.
+        Token::RawSlice(words) => {
+            SqlArg::Bytes(words.iter().flat_map(|word| word.to_be_bytes()).collect())
+        }
+        // Structs, enums and arrays in tuples or vectors have no column.
+        _ => {
+            return Err(EcalError::Unsupported {
+                type_id,
+                reason: format!("storing {tok:?} in a column"),
+            })
         }
-        // Token::Array(elems) => {
-        //     format!(
-        //         "[{}]",
-        //         elems
-        //             .iter()
-        //             .map(tok_to_string)
-        //             .collect::<Vec<String>>()
-        //             .join(", ")
-        //     )
-        // }
-        // Token::Enum(enum_selector) => {
-        //     let (_, tok, _) = *enum_selector.to_owned();
-        //     format!("ZZZ({})", tok_to_string(&tok))
-        // }
-        // Token::Struct(fields) => "STRUCT".to_string(),
-        Token::Unit => "()".to_string(),
-        _ => unimplemented!("{tok:?}"),
-        // _ => "ZZZ".to_string(),
+    })
+}
+
+// The values of a field stored in columns, one per column, see
+// `field_columns`.
+pub(super) fn scalar_args(
+    type_id: usize,
+    param_type: &ParamType,
+    tok: &Token,
+) -> EcalResult<Vec<SqlArg>> {
+    Ok(match (param_type, tok) {
+        (_, Token::Unit) => vec![],
+        (ParamType::Tuple(types), Token::Tuple(toks)) => {
+            let mut args = vec![];
+            for (param_type, tok) in types.iter().zip(toks) {
+                args.extend(scalar_args(type_id, param_type, tok)?);
+            }
+            args
+        }
+        (ParamType::Vector(elt), Token::Vector(toks)) => {
+            vec![SqlArg::Bytes(vector_bytes(elt, toks)?)]
+        }
+        _ => vec![tok_to_arg(type_id, tok)?],
+    })
+}
+
+// `Vec<u8>` is stored as its bytes, other vectors as their length followed
+// by the ABI encoding of their elements, see `ecal_load::decode_vector`.
+pub(super) fn vector_bytes(elt: &ParamType, toks: &[Token]) -> EcalResult<Vec<u8>> {
+    let encode_error = |reason: String| EcalError::Encode {
+        type_id: None,
+        reason,
+    };
+    if *elt == ParamType::U8 {
+        return toks
+            .iter()
+            .map(|tok| match tok {
+                Token::U8(b) => Ok(*b),
+                _ => Err(encode_error(format!("expected a u8, got {tok:?}"))),
+            })
+            .collect();
+    }
+    let encoded = ABIEncoder::encode(&[Token::Array(toks.to_vec())])
+        .map_err(|e| encode_error(e.to_string()))?
+        .resolve(0);
+    let mut bytes = (toks.len() as u64).to_be_bytes().to_vec();
+    bytes.extend(encoded);
+    Ok(bytes)
+}
+
+// The name of the variant of a fieldless enum, its value in the database.
+pub(super) fn variant_name(decl: &TypeDeclaration, tok: &Token) -> String {
+    let (discriminant, _, _) = tok.as_enum();
+    decl.components.as_ref().unwrap()[discriminant as usize]
+        .name
+        .clone()
+}
+
+// The columns of a field, see `SQLTableBuilder::process_param_type`. Arrays
+// of entities have tables of their own, `()` has no value.
+pub(super) fn field_columns(abi: &crate::ABI, name: &str, field: &TypeApplication) -> Vec<String> {
+    let decl = abi.type_declaration(field.type_id);
+    if decl.is_byte_array(abi) || column_option(abi, field).is_some() {
+        vec![format!("\"{name}\"")]
+    } else if decl.is_array() || decl.type_field == "()" {
+        vec![]
+    } else if decl.is_fieldless_enum(abi) {
+        vec![format!("\"{name}\"")]
+    } else if (decl.is_struct() || decl.is_enum()) && !decl.is_std_scalar() {
+        vec![format!("\"{name}Id\"")]
+    } else if decl.is_tuple() {
+        decl.components
+            .iter()
+            .flatten()
+            .enumerate()
+            .flat_map(|(i, elt)| field_columns(abi, &tuple_element_column(name, i), elt))
+            .collect()
+    } else {
+        vec![format!("\"{name}\"")]
+    }
+}
+
+// The placeholders for the key columns of a row.
+struct RowKey {
+    // The content hash, unique in every table, see `CONTENT_HASH_COLUMN`.
+    hash: String,
+    // The id, if it's known before the insert, see `PrimaryKeys::ContentHash`.
+    id: Option<String>,
+    // For keyed entities, the column to update the row with the same value
+    // of, see `EntityKeys`.
+    upsert: Option<String>,
+}
+
+// A row saved by a `SaveStmtBuilder`, by the placeholder of its content hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct RowRef {
+    table: String,
+    hash: String,
+}
+
+impl RowRef {
+    // The subquery selecting the id of the row from the rows returned by the
+    // insert into its table.
+    fn id(&self) -> String {
+        format!(
+            "(SELECT id FROM \"{}_rows\" WHERE {CONTENT_HASH_COLUMN} = {})",
+            self.table, self.hash
+        )
     }
 }
 
-// TODO: derive Hash for Token instead.
-fn hash_tokens(tokens: &Vec<Token>) -> u64 {
-    use std::hash::{Hash, Hasher};
-    let mut hasher = std::collections::hash_map::DefaultHasher::new();
-    let s: String = format!("{tokens:#?}");
-    s.hash(&mut hasher);
-    hasher.finish()
+// The rows of one table, inserted with one multi-row INSERT unless rows with
+// the same content hashes exist, and returned either way.
+//
+// The unique content hash makes this safe when indexers write the same
+// values concurrently: the insert conflicting with a row inserted by another
+// transaction waits for it, and the no-op update returns it. A select of the
+// existing row couldn't see it, it's newer than the statement.
+//
+// An insert can't update a row twice, so rows with the same content hash are
+// only added once. Keyed entities, see `EntityKeys`, update the row with the
+// same key instead, and one with another value goes into the next statement,
+// see `SaveStmtBuilder::conflicts_with`.
+#[derive(Debug)]
+struct TableRows {
+    table: String,
+    // For keyed entities, the key column, see `RowKey`.
+    upsert: Option<String>,
+    // The columns of all rows. Enum rows only have the columns of their
+    // variant, the others are NULL.
+    columns: Vec<String>,
+    // The values of the rows, by column.
+    rows: Vec<HashMap<String, String>>,
+    hashes: HashSet<String>,
+    references: Vec<String>,
+}
+
+impl TableRows {
+    fn push(
+        &mut self,
+        hash: &str,
+        columns: Vec<String>,
+        values: Vec<String>,
+        references: Vec<String>,
+    ) {
+        if !self.hashes.insert(hash.to_string()) {
+            return;
+        }
+        for column in &columns {
+            if !self.columns.contains(column) {
+                self.columns.push(column.clone());
+            }
+        }
+        for table in references {
+            if !self.references.contains(&table) {
+                self.references.push(table);
+            }
+        }
+        self.rows.push(columns.into_iter().zip(values).collect());
+    }
+
+    fn insert(&self) -> String {
+        let table = &self.table;
+        let rows: Vec<String> = self
+            .rows
+            .iter()
+            .map(|row| {
+                let values: Vec<&str> = self
+                    .columns
+                    .iter()
+                    .map(|column| row.get(column).map_or("NULL", String::as_str))
+                    .collect();
+                format!("({})", values.join(", "))
+            })
+            .collect();
+        // A content id equal to that of a row with another content hash
+        // isn't a conflict, the insert fails on the primary key.
+        let target = self
+            .upsert
+            .clone()
+            .unwrap_or_else(|| CONTENT_HASH_COLUMN.to_string());
+        let mut sets: Vec<String> = match self.upsert {
+            Some(_) => self
+                .columns
+                .iter()
+                .filter(|c| c.as_str() != "id" && **c != target)
+                .map(|c| format!("{c} = EXCLUDED.{c}"))
+                .collect(),
+            None => vec![],
+        };
+        // Without anything to update, the row isn't returned.
+        if sets.is_empty() {
+            sets.push(format!("{target} = EXCLUDED.{target}"));
+        }
+        format!(
+            "\"{table}_rows\" AS (INSERT INTO \"{table}\" ({}) VALUES {} ON CONFLICT ({target}) DO UPDATE SET {} RETURNING id, {CONTENT_HASH_COLUMN})",
+            self.columns.join(", "),
+            rows.join(", "),
+            sets.join(", ")
+        )
+    }
+}
+
+// The rows of an array table linking the elements of arrays to the values
+// holding them, inserted after both. The value may exist already, with the
+// same elements, so the links may too. Parent and position are unique, which
+// also makes this safe when indexers link the same value concurrently.
+#[derive(Debug)]
+struct LinkRows {
+    table: String,
+    parent_column: String,
+    // The parent, position and element of every link.
+    rows: Vec<(RowRef, usize, RowRef)>,
+    keys: HashSet<(String, usize)>,
+}
+
+impl LinkRows {
+    fn insert(&self) -> String {
+        let LinkRows {
+            table,
+            parent_column,
+            ..
+        } = self;
+        let rows: Vec<String> = self
+            .rows
+            .iter()
+            .map(|(parent, position, elt)| format!("({}, {}, {position})", elt.id(), parent.id()))
+            .collect();
+        format!(
+            "\"{table}_{parent_column}_links\" AS (INSERT INTO \"{table}\" (id, {parent_column}, position) VALUES {} ON CONFLICT ({parent_column}, position) DO UPDATE SET id = EXCLUDED.id)",
+            rows.join(", ")
+        )
+    }
+}
+
+// The links of updated values at the positions their new arrays have no
+// element at, deleted from an array table. The others are updated, see
+// `LinkRows`.
+#[derive(Debug)]
+struct Unlinks {
+    table: String,
+    parent_column: String,
+    // The updated values, with the positions of their elements.
+    parents: Vec<(RowRef, Vec<usize>)>,
+}
+
+impl Unlinks {
+    fn delete(&self) -> String {
+        let Unlinks {
+            table,
+            parent_column,
+            ..
+        } = self;
+        let conditions: Vec<String> = self
+            .parents
+            .iter()
+            .map(|(parent, positions)| {
+                let positions: Vec<String> = positions.iter().map(usize::to_string).collect();
+                let others = if positions.is_empty() {
+                    "".to_string()
+                } else {
+                    format!(" AND position NOT IN ({})", positions.join(", "))
+                };
+                format!("({parent_column} = {}{others})", parent.id())
+            })
+            .collect();
+        format!(
+            "\"{table}_{parent_column}_unlinks\" AS (DELETE FROM \"{table}\" WHERE {})",
+            conditions.join(" OR ")
+        )
+    }
+}
+
+// The sha256 of the type name of `decl` and the ABI encoding of the value.
+// The name, unlike the type id, doesn't depend on the script.
+pub(super) fn content_hash(decl: &TypeDeclaration, value: &Token) -> EcalResult<[u8; 32]> {
+    let encoded = ABIEncoder::encode(std::slice::from_ref(value))
+        .map_err(|e| EcalError::Encode {
+            type_id: Some(decl.type_id),
+            reason: e.to_string(),
+        })?
+        .resolve(0);
+    let type_name = &decl.type_field;
+    let mut hasher = Sha256::new();
+    hasher.update((type_name.len() as u64).to_be_bytes());
+    hasher.update(type_name.as_bytes());
+    hasher.update(&encoded);
+    Ok(hasher.finalize().into())
+}
+
+// The id of a row with `PrimaryKeys::ContentHash`, the first 63 bits of its
+// content hash. It's positive, and never 0, which stands for no row. Ids may
+// collide, the full hash tells the rows apart, see `TableRows::insert`.
+pub(super) fn content_id(hash: [u8; 32]) -> i64 {
+    let id = u64::from_be_bytes(hash[..8].try_into().unwrap()) >> 1;
+    id.max(1) as i64
+}
+
+// The bytes of a `[Option<u8>; N]` up to the first `None`.
+pub(super) fn some_prefix(tok: &Token) -> Vec<u8> {
+    tok.as_array()
+        .iter()
+        .map_while(|elt| match elt.as_enum() {
+            (1, Token::U8(b), _) => Some(b),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fuels::types::{StaticStringToken, U256};
+
+    #[test]
+    fn scalar_args_bind_a_value_per_column() {
+        let cases = vec![
+            (ParamType::U8, Token::U8(255), vec![SqlArg::Int(255)]),
+            (ParamType::U16, Token::U16(65535), vec![SqlArg::Int(65535)]),
+            (
+                ParamType::U32,
+                Token::U32(u32::MAX),
+                vec![SqlArg::Int(u32::MAX as i64)],
+            ),
+            (
+                ParamType::U64,
+                Token::U64(u64::MAX),
+                vec![SqlArg::Numeric(u64::MAX.to_string())],
+            ),
+            (
+                ParamType::U128,
+                Token::U128((1 << 64) | 2),
+                vec![SqlArg::Text(format!("{:032x}", (1u128 << 64) | 2))],
+            ),
+            (
+                ParamType::U256,
+                Token::U256(U256::from(258)),
+                vec![SqlArg::Text(format!("{:064x}", 258))],
+            ),
+            (
+                ParamType::B256,
+                Token::B256([1; 32]),
+                vec![SqlArg::Text("01".repeat(32))],
+            ),
+            (ParamType::Bool, Token::Bool(true), vec![SqlArg::Bool(true)]),
+            (
+                ParamType::StringArray(5),
+                Token::StringArray(StaticStringToken::new("hello".to_string(), Some(5))),
+                vec![SqlArg::Text("hello".to_string())],
+            ),
+            (
+                ParamType::String,
+                Token::String("hello".to_string()),
+                vec![SqlArg::Text("hello".to_string())],
+            ),
+            (
+                ParamType::Bytes,
+                Token::Bytes(vec![0, 1, 255]),
+                vec![SqlArg::Bytes(vec![0, 1, 255])],
+            ),
+            (
+                ParamType::RawSlice,
+                Token::RawSlice(vec![1, 2]),
+                vec![SqlArg::Bytes(vec![
+                    0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2,
+                ])],
+            ),
+            (
+                ParamType::Vector(Box::new(ParamType::U8)),
+                Token::Vector(vec![Token::U8(1), Token::U8(2)]),
+                vec![SqlArg::Bytes(vec![1, 2])],
+            ),
+            (
+                ParamType::Tuple(vec![ParamType::U64, ParamType::Bool]),
+                Token::Tuple(vec![Token::U64(42), Token::Bool(true)]),
+                vec![SqlArg::Numeric("42".to_string()), SqlArg::Bool(true)],
+            ),
+            (ParamType::Unit, Token::Unit, vec![]),
+        ];
+        for (param_type, tok, expected) in cases {
+            assert_eq!(
+                scalar_args(0, &param_type, &tok).unwrap(),
+                expected,
+                "{param_type:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn scalar_args_reject_values_without_a_column() {
+        let param_type = ParamType::Tuple(vec![ParamType::Struct {
+            fields: vec![ParamType::U8],
+            generics: vec![],
+        }]);
+        let tok = Token::Tuple(vec![Token::Struct(vec![Token::U8(1)])]);
+        let err = scalar_args(7, &param_type, &tok).unwrap_err();
+        assert!(
+            matches!(err, EcalError::Unsupported { type_id: 7, .. }),
+            "{err:?}"
+        );
+    }
 }
@@ -0,0 +1,155 @@
+use fuel_asm::PanicReason;
+use fuel_vm::error::PanicOrBug;
+
+use std::sync::{Arc, Mutex};
+
+pub type EcalResult<T> = Result<T, EcalError>;
+
+// Errors returned by ECAL handlers. `Vm` errors (out of gas, memory access
+// out of bounds) are handed to the VM as they are, everything else makes the
+// VM panic with `PanicReason::EcalError` and is recorded in the `ErrorReport`.
+#[derive(Debug)]
+pub enum EcalError {
+    Vm(PanicOrBug),
+    // rA named no host function, see `host_functions`.
+    UnknownFunction(u64),
+    UnknownTypeId(usize),
+    UnknownTypeName(String),
+    Unsupported {
+        type_id: usize,
+        reason: String,
+    },
+    Decode {
+        type_id: Option<usize>,
+        reason: String,
+    },
+    Encode {
+        type_id: Option<usize>,
+        reason: String,
+    },
+    Utf8(std::string::FromUtf8Error),
+    Sql {
+        type_id: usize,
+        sql: String,
+        source: sqlx::Error,
+    },
+    // Running the statements of a `WriteBuffer` failed.
+    Flush {
+        sql: String,
+        source: sqlx::Error,
+    },
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    Sandbox(String),
+    NoDatabase,
+    Replay(String),
+}
+
+impl EcalError {
+    pub fn type_id(&self) -> Option<usize> {
+        match self {
+            EcalError::UnknownTypeId(type_id)
+            | EcalError::Unsupported { type_id, .. }
+            | EcalError::Sql { type_id, .. } => Some(*type_id),
+            EcalError::Decode { type_id, .. } | EcalError::Encode { type_id, .. } => *type_id,
+            _ => None,
+        }
+    }
+
+    pub fn sql(&self) -> Option<&str> {
+        match self {
+            EcalError::Sql { sql, .. } | EcalError::Flush { sql, .. } => Some(sql),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for EcalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EcalError::Vm(e) => write!(f, "VM error: {e:?}"),
+            EcalError::UnknownFunction(number) => {
+                write!(f, "unknown ECAL function number {number}")
+            }
+            EcalError::UnknownTypeId(type_id) => write!(f, "unknown type id {type_id}"),
+            EcalError::UnknownTypeName(name) => write!(f, "unknown type '{name}'"),
+            EcalError::Unsupported { reason, .. } => write!(f, "unsupported: {reason}"),
+            EcalError::Decode { reason, .. } => write!(f, "failed to decode: {reason}"),
+            EcalError::Encode { reason, .. } => write!(f, "failed to encode: {reason}"),
+            EcalError::Utf8(e) => write!(f, "invalid UTF-8: {e}"),
+            EcalError::Sql { source, .. } => write!(f, "SQL error: {source}"),
+            EcalError::Flush { source, .. } => {
+                write!(f, "failed to flush the buffered saves: {source}")
+            }
+            EcalError::Io { path, source } => write!(f, "'{path}': {source}"),
+            EcalError::Sandbox(reason) => write!(f, "{reason}"),
+            EcalError::NoDatabase => write!(f, "no database connection"),
+            EcalError::Replay(reason) => write!(f, "replay: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for EcalError {}
+
+impl From<PanicOrBug> for EcalError {
+    fn from(e: PanicOrBug) -> Self {
+        EcalError::Vm(e)
+    }
+}
+
+impl From<PanicReason> for EcalError {
+    fn from(reason: PanicReason) -> Self {
+        EcalError::Vm(reason.into())
+    }
+}
+
+impl From<std::string::FromUtf8Error> for EcalError {
+    fn from(e: std::string::FromUtf8Error) -> Self {
+        EcalError::Utf8(e)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EcalFailure {
+    pub number: u64,
+    pub name: &'static str,
+    pub type_id: Option<usize>,
+    pub sql: Option<String>,
+    pub error: String,
+}
+
+impl std::fmt::Display for EcalFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ECAL {} ({}): {}", self.number, self.name, self.error)?;
+        if let Some(type_id) = self.type_id {
+            write!(f, "\n  type_id: {type_id}")?;
+        }
+        if let Some(sql) = &self.sql {
+            write!(f, "\n  sql: {sql}")?;
+        }
+        Ok(())
+    }
+}
+
+// Failed ECALs of one script run. Shared between the clones of `MyEcal`, so
+// the host can still read it after the VM is done.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorReport(Arc<Mutex<Vec<EcalFailure>>>);
+
+impl ErrorReport {
+    pub fn push(&self, number: u64, name: &'static str, error: &EcalError) {
+        self.0.lock().unwrap().push(EcalFailure {
+            number,
+            name,
+            type_id: error.type_id(),
+            sql: error.sql().map(str::to_string),
+            error: error.to_string(),
+        });
+    }
+
+    pub fn failures(&self) -> Vec<EcalFailure> {
+        self.0.lock().unwrap().clone()
+    }
+}
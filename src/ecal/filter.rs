@@ -0,0 +1,296 @@
+use fuels::core::codec::ABIDecoder;
+
+use super::ecal_save::{content_hash, field_columns, scalar_args, some_prefix, variant_name};
+use super::{EcalError, EcalResult, MyEcal};
+use crate::db::SqlArg;
+use crate::extensions::*;
+use crate::sql::sql_table_builder::{fieldless_enum_type, CONTENT_HASH_COLUMN};
+
+// Which rows of a table an ECAL applies to. ecal-lib's `Filter<T>` serializes
+// it as a tag byte followed by the operands, integers as big-endian u64s:
+//
+//   0                              every row
+//   1 field op size value[size]    the field with index `field` compares to
+//                                  `value`, in its ABI encoding, see `Op`
+//   2 lhs rhs                      both filters
+//   3 lhs rhs                      either filter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    Any,
+    Field {
+        field: usize,
+        op: Op,
+        value: Vec<u8>,
+    },
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+}
+
+// A byte, in the order of the variants. Only scalars stored in one column
+// can be ordered, anything can be compared for equality.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn sql(self) -> &'static str {
+        match self {
+            Op::Eq => "=",
+            Op::Ne => "<>",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+        }
+    }
+}
+
+// Filters nest, a script could otherwise make the host overflow its stack.
+const MAX_DEPTH: usize = 64;
+
+impl Filter {
+    pub fn parse(bytes: &[u8]) -> EcalResult<Filter> {
+        let mut reader = Reader(bytes);
+        let filter = Filter::read(&mut reader, 0)?;
+        if !reader.0.is_empty() {
+            return Err(decode_error(format!(
+                "{} trailing bytes after the filter",
+                reader.0.len()
+            )));
+        }
+        Ok(filter)
+    }
+
+    fn read(reader: &mut Reader, depth: usize) -> EcalResult<Filter> {
+        if depth > MAX_DEPTH {
+            return Err(decode_error(format!(
+                "filters nest more than {MAX_DEPTH} deep"
+            )));
+        }
+        let filter = match reader.u8()? {
+            0 => Filter::Any,
+            1 => {
+                let field = reader.u64()? as usize;
+                let op = match reader.u8()? {
+                    0 => Op::Eq,
+                    1 => Op::Ne,
+                    2 => Op::Lt,
+                    3 => Op::Le,
+                    4 => Op::Gt,
+                    5 => Op::Ge,
+                    op => return Err(decode_error(format!("unknown filter op {op}"))),
+                };
+                let size = reader.u64()? as usize;
+                let value = reader.bytes(size)?.to_vec();
+                Filter::Field { field, op, value }
+            }
+            tag @ (2 | 3) => {
+                let lhs = Box::new(Filter::read(reader, depth + 1)?);
+                let rhs = Box::new(Filter::read(reader, depth + 1)?);
+                if tag == 2 {
+                    Filter::And(lhs, rhs)
+                } else {
+                    Filter::Or(lhs, rhs)
+                }
+            }
+            tag => return Err(decode_error(format!("unknown filter tag {tag}"))),
+        };
+        Ok(filter)
+    }
+
+    // The SQL condition selecting the rows of `table`, the table or alias of
+    // the struct `type_id`. The values are appended to `args`.
+    pub fn condition(
+        &self,
+        ecal: &MyEcal,
+        type_id: usize,
+        table: &str,
+        args: &mut Vec<SqlArg>,
+    ) -> EcalResult<String> {
+        match self {
+            Filter::Any => Ok("TRUE".to_string()),
+            Filter::And(lhs, rhs) => Ok(format!(
+                "({}) AND ({})",
+                lhs.condition(ecal, type_id, table, args)?,
+                rhs.condition(ecal, type_id, table, args)?
+            )),
+            Filter::Or(lhs, rhs) => Ok(format!(
+                "({}) OR ({})",
+                lhs.condition(ecal, type_id, table, args)?,
+                rhs.condition(ecal, type_id, table, args)?
+            )),
+            Filter::Field { field, op, value } => {
+                let decl = ecal.abi.type_declaration(type_id);
+                let unsupported = |reason: String| EcalError::Unsupported { type_id, reason };
+                let field = decl
+                    .components
+                    .as_ref()
+                    .and_then(|fields| fields.get(*field))
+                    .ok_or_else(|| {
+                        unsupported(format!("'{}' has no field {field}", decl.type_field))
+                    })?;
+                let field_decl = ecal.abi.type_declaration(field.type_id);
+                let param_type = super::param_type(&ecal.abi, field.type_id)?;
+                let token = ABIDecoder::new(super::DECODER_CONFIG)
+                    .decode(&param_type, value)
+                    .map_err(|e| EcalError::Decode {
+                        type_id: Some(field.type_id),
+                        reason: e.to_string(),
+                    })?;
+
+                let mut bind = |arg: SqlArg| {
+                    args.push(arg.clone());
+                    arg.placeholder(args.len())
+                };
+                let name = &field.name;
+                let eq = if field_decl.is_byte_array(&ecal.abi) {
+                    let value = bind(SqlArg::Bytes(some_prefix(&token)));
+                    format!("{table}.\"{name}\" = {value}")
+                } else if field_decl.is_struct() && !field_decl.is_std_scalar() {
+                    // Nested structs are matched by the content hash of their
+                    // row, see `SaveStmtBuilder::row_key`.
+                    let struct_name = field_decl.struct_or_enum_name().unwrap();
+                    let value = bind(SqlArg::Bytes(content_hash(&field_decl, &token)?.to_vec()));
+                    format!("{table}.\"{name}Id\" IN (SELECT id FROM \"{struct_name}\" WHERE {CONTENT_HASH_COLUMN} = {value})")
+                } else if field_decl.is_fieldless_enum(&ecal.abi) {
+                    let value = bind(SqlArg::Text(variant_name(&field_decl, &token)));
+                    let enum_type = fieldless_enum_type(&field_decl.struct_or_enum_name().unwrap());
+                    format!("{table}.\"{name}\" = {value}::{enum_type}")
+                } else if field_decl.is_enum() || field_decl.is_array() {
+                    return Err(unsupported(format!(
+                        "filtering on '{name}' of type '{}'",
+                        field_decl.type_field
+                    )));
+                } else {
+                    // Tuples have a column per element, `()` none.
+                    let columns = field_columns(&ecal.abi, name, field);
+                    let values = scalar_args(field.type_id, &param_type, &token)?;
+                    if let ([column], [value]) = (columns.as_slice(), values.as_slice()) {
+                        let value = bind(value.clone());
+                        return Ok(format!("{table}.{column} {} {value}", op.sql()));
+                    }
+                    let conditions: Vec<String> = columns
+                        .iter()
+                        .zip(values)
+                        .map(|(column, arg)| format!("{table}.{column} = {}", bind(arg)))
+                        .collect();
+                    if conditions.is_empty() {
+                        "TRUE".to_string()
+                    } else {
+                        conditions.join(" AND ")
+                    }
+                };
+                match op {
+                    Op::Eq => Ok(eq),
+                    Op::Ne => Ok(format!("NOT ({eq})")),
+                    _ => Err(unsupported(format!(
+                        "ordering '{name}' of type '{}'",
+                        field_decl.type_field
+                    ))),
+                }
+            }
+        }
+    }
+}
+
+// The order of the rows `load_many` returns. ecal-lib's `Order<T>` serializes
+// it as the fields to order by, most significant first:
+//
+//   (field desc)*    the field with index `field`, descending if `desc` is 1
+//
+// Ties are broken by id, so pages of the same query don't overlap.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OrderBy(Vec<(usize, bool)>);
+
+impl OrderBy {
+    pub fn parse(bytes: &[u8]) -> EcalResult<OrderBy> {
+        let mut reader = Reader(bytes);
+        let mut fields = vec![];
+        while !reader.0.is_empty() {
+            let field = reader.u64()? as usize;
+            let desc = match reader.u8()? {
+                0 => false,
+                1 => true,
+                desc => return Err(decode_error(format!("unknown order direction {desc}"))),
+            };
+            fields.push((field, desc));
+        }
+        Ok(OrderBy(fields))
+    }
+
+    // The SQL ORDER BY list for the rows of `table`, see `Filter::condition`.
+    // Only fields stored in one column can be ordered by, not nested structs
+    // or enums.
+    pub fn sql(&self, ecal: &MyEcal, type_id: usize, table: &str) -> EcalResult<String> {
+        let decl = ecal.abi.type_declaration(type_id);
+        let unsupported = |reason: String| EcalError::Unsupported { type_id, reason };
+        let mut terms = vec![];
+        for (field, desc) in &self.0 {
+            let field = decl
+                .components
+                .as_ref()
+                .and_then(|fields| fields.get(*field))
+                .ok_or_else(|| {
+                    unsupported(format!("'{}' has no field {field}", decl.type_field))
+                })?;
+            let field_decl = ecal.abi.type_declaration(field.type_id);
+            let columns = field_columns(&ecal.abi, &field.name, field);
+            let [column] = columns.as_slice() else {
+                return Err(unsupported(format!(
+                    "ordering by '{}' of type '{}'",
+                    field.name, field_decl.type_field
+                )));
+            };
+            if (field_decl.is_struct() || field_decl.is_enum())
+                && !field_decl.is_std_scalar()
+                && !field_decl.is_fieldless_enum(&ecal.abi)
+            {
+                return Err(unsupported(format!(
+                    "ordering by '{}' of type '{}'",
+                    field.name, field_decl.type_field
+                )));
+            }
+            let direction = if *desc { "DESC" } else { "ASC" };
+            terms.push(format!("{table}.{column} {direction}"));
+        }
+        terms.push(format!("{table}.id"));
+        Ok(terms.join(", "))
+    }
+}
+
+fn decode_error(reason: String) -> EcalError {
+    EcalError::Decode {
+        type_id: None,
+        reason,
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> EcalResult<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(decode_error(format!(
+                "filter ends after {} of {n} bytes",
+                self.0.len()
+            )));
+        }
+        let (bytes, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> EcalResult<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u64(&mut self) -> EcalResult<u64> {
+        Ok(u64::from_be_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
@@ -0,0 +1,64 @@
+// Gas charged by the host functions for the work they do outside of the VM,
+// so that the script gas limit also bounds the database work of a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GasCosts {
+    // Flat cost of every ECAL.
+    pub call: u64,
+    // Per byte of VM memory decoded into tokens, or encoded back.
+    pub decoded_byte: u64,
+    // Per SQL statement generated, e.g. every CTE of a save.
+    pub statement: u64,
+    // Per row inserted, updated or returned by the database.
+    pub row: u64,
+    // Per byte of output printed.
+    pub printed_byte: u64,
+}
+
+impl Default for GasCosts {
+    fn default() -> Self {
+        Self {
+            call: 100,
+            decoded_byte: 1,
+            statement: 1_000,
+            row: 500,
+            printed_byte: 1,
+        }
+    }
+}
+
+impl GasCosts {
+    // GAS_COST_CALL, GAS_COST_DECODED_BYTE, GAS_COST_STATEMENT, GAS_COST_ROW
+    // and GAS_COST_PRINTED_BYTE, with the defaults for the unset ones.
+    pub fn from_env() -> Self {
+        fn var(name: &str, default: u64) -> u64 {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        }
+        let default = Self::default();
+        Self {
+            call: var("GAS_COST_CALL", default.call),
+            decoded_byte: var("GAS_COST_DECODED_BYTE", default.decoded_byte),
+            statement: var("GAS_COST_STATEMENT", default.statement),
+            row: var("GAS_COST_ROW", default.row),
+            printed_byte: var("GAS_COST_PRINTED_BYTE", default.printed_byte),
+        }
+    }
+
+    pub fn decoding(&self, bytes: u64) -> u64 {
+        self.decoded_byte.saturating_mul(bytes)
+    }
+
+    pub fn statements(&self, count: u64) -> u64 {
+        self.statement.saturating_mul(count)
+    }
+
+    pub fn rows(&self, count: u64) -> u64 {
+        self.row.saturating_mul(count)
+    }
+
+    pub fn printing(&self, bytes: u64) -> u64 {
+        self.printed_byte.saturating_mul(bytes)
+    }
+}
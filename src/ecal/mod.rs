@@ -1,13 +1,38 @@
-use fuel_asm::RegId;
-use fuel_vm::{error::SimpleResult, interpreter::EcalHandler, prelude::Interpreter};
+use fuel_abi_types::abi::program::TypeApplication;
+use fuel_asm::{PanicReason, RegId};
+use fuel_vm::{
+    error::SimpleResult,
+    interpreter::EcalHandler,
+    prelude::{Interpreter, MemoryRange},
+};
 use fuels::core::codec::DecoderConfig;
+use fuels::types::param_types::ParamType;
+
+use std::collections::HashMap;
+use std::path::PathBuf;
 
-use sqlx::{Pool, Postgres};
+use crate::db::Db;
+use crate::sql::sql_table_builder::{EntityKeys, PrimaryKeys};
 
+mod ecal_delete;
 mod ecal_load;
 mod ecal_print;
+mod ecal_read_file;
 mod ecal_save;
 mod ecal_type_id;
+mod error;
+mod filter;
+mod gas;
+mod record;
+mod registry;
+mod write_buffer;
+
+pub use error::ErrorReport;
+use error::{EcalError, EcalResult};
+pub use gas::GasCosts;
+pub use record::{read_recording, EcalMode, Recorder, Replayer};
+pub use registry::host_functions;
+pub use write_buffer::WriteBuffer;
 
 fuels::macros::abigen!(Contract(
     name = "MyContract",
@@ -22,7 +47,33 @@ const DECODER_CONFIG: DecoderConfig = DecoderConfig {
 #[derive(Debug, Clone)]
 pub struct MyEcal {
     pub abi: crate::ABI,
-    pub db_pool: Pool<Postgres>,
+    // `None` when replaying a recording.
+    pub db: Option<Db>,
+    // `read_file` only serves files under this directory.
+    pub files_root: PathBuf,
+    pub errors: ErrorReport,
+    pub gas_costs: GasCosts,
+    pub mode: EcalMode,
+    // When set, `save` buffers its writes instead of running them.
+    pub write_buffer: Option<WriteBuffer>,
+    // Must match the schema, see `SQLTableBuilder::primary_keys`.
+    pub primary_keys: PrimaryKeys,
+    // Must match the schema, see `SQLTableBuilder::entity_keys`.
+    pub entity_keys: EntityKeys,
+}
+
+impl MyEcal {
+    fn db(&self) -> EcalResult<&Db> {
+        self.db.as_ref().ok_or(EcalError::NoDatabase)
+    }
+
+    fn save_stmt_builder(&self) -> ecal_save::SaveStmtBuilder {
+        ecal_save::SaveStmtBuilder::new(
+            self.abi.clone(),
+            self.primary_keys,
+            self.entity_keys.clone(),
+        )
+    }
 }
 
 impl EcalHandler for MyEcal {
@@ -34,14 +85,95 @@ impl EcalHandler for MyEcal {
         _rd: RegId,
     ) -> SimpleResult<()> {
         let a = vm.registers()[ra];
+        let Some(function) = host_functions().get(a) else {
+            // Panic the VM, not the host: the script gets a Panic receipt.
+            let e = EcalError::UnknownFunction(a);
+            println!("ECAL {a} failed: {e}");
+            vm.ecal_state().errors.push(a, "unknown", &e);
+            return Err(PanicReason::EcalError.into());
+        };
         #[cfg(debug_assertions)]
-        println!("CALLING ECAL {a}");
-        match a {
-            3 => ecal_save::save(vm, rb),
-            4 => ecal_load::load(vm, rb),
-            7 => ecal_type_id::type_id(vm, rb),
-            8 => ecal_print::println(vm, rb),
-            _ => panic!("Unexpected ECAL function number {a}"),
+        println!("CALLING ECAL {a} ({})", function.name);
+        let call_cost = vm.ecal_state().gas_costs.call;
+        vm.gas_charge(call_cost)?;
+        let result = match vm.ecal_state().mode.clone() {
+            EcalMode::Live => function.handler.call(vm, rb),
+            EcalMode::Record(recorder) => recorder.record(vm, function, rb),
+            EcalMode::Replay(replayer) => replayer.replay(vm, function, rb),
+        };
+        match result {
+            Ok(()) => Ok(()),
+            Err(EcalError::Vm(e)) => Err(e),
+            Err(e) => {
+                println!("ECAL::{} failed: {e}", function.name);
+                vm.ecal_state().errors.push(a, function.name, &e);
+                Err(PanicReason::EcalError.into())
+            }
         }
     }
 }
+
+// Reads `N` consecutive u64 words, e.g. an arguments tuple, at the address in rB.
+fn read_words<S, Tx, const N: usize>(
+    vm: &Interpreter<S, Tx, MyEcal>,
+    rb: RegId,
+) -> EcalResult<[u64; N]> {
+    let words = read_u64s(vm, vm.registers()[rb], N)?;
+    Ok(words.try_into().unwrap())
+}
+
+fn read_u64s<S, Tx>(vm: &Interpreter<S, Tx, MyEcal>, addr: u64, n: usize) -> EcalResult<Vec<u64>> {
+    let bytes = read_memory(vm, addr, n as u64 * 8)?;
+    Ok(bytes
+        .chunks_exact(8)
+        .map(|chunk| u64::from_be_bytes(chunk.try_into().unwrap()))
+        .collect())
+}
+
+fn read_memory<S, Tx>(
+    vm: &Interpreter<S, Tx, MyEcal>,
+    addr: u64,
+    size: u64,
+) -> EcalResult<Vec<u8>> {
+    let r = MemoryRange::new(addr, size)?;
+    Ok(vm.memory()[r.usizes()].to_vec())
+}
+
+// Copies `bytes` to newly allocated heap memory and returns its address
+// through rB.
+fn write_output<S, Tx>(
+    vm: &mut Interpreter<S, Tx, MyEcal>,
+    rb: RegId,
+    bytes: &[u8],
+) -> EcalResult<()> {
+    vm.allocate(bytes.len() as u64)?;
+    let o = MemoryRange::new(vm.registers()[RegId::HP], bytes.len())?;
+    vm.memory_mut()[o.usizes()].copy_from_slice(bytes);
+    vm.registers_mut()[rb] = o.start as u64;
+    Ok(())
+}
+
+fn param_type(abi: &crate::ABI, type_id: usize) -> EcalResult<ParamType> {
+    abi.param_types
+        .get(&type_id)
+        .cloned()
+        .ok_or(EcalError::UnknownTypeId(type_id))
+}
+
+// The param type of a field, also of generic types like `Option<T>`, which
+// have no entry in `ABI::param_types`.
+fn application_param_type(
+    abi: &crate::ABI,
+    application: &TypeApplication,
+) -> EcalResult<ParamType> {
+    if application.type_arguments.is_none() {
+        return param_type(abi, application.type_id);
+    }
+    let type_lookup = HashMap::from_iter(abi.types.clone());
+    ParamType::try_from_type_application(application, &type_lookup).map_err(|e| {
+        EcalError::Unsupported {
+            type_id: application.type_id,
+            reason: e.to_string(),
+        }
+    })
+}
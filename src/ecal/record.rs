@@ -0,0 +1,335 @@
+use anyhow::Context;
+use fuel_asm::{PanicReason, RegId};
+use fuel_vm::error::PanicOrBug;
+use fuel_vm::prelude::{Interpreter, MemoryRange};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use super::registry::{ArgLayout, EcalFunction, Replay};
+use super::{EcalError, EcalResult, MyEcal};
+
+#[derive(Debug, Clone, Default)]
+pub enum EcalMode {
+    // Run the handlers against the database.
+    #[default]
+    Live,
+    // Run the handlers and append every invocation to a recording.
+    Record(Recorder),
+    // Serve the ECALs from a recording, without a database.
+    Replay(Replayer),
+}
+
+// One ECAL invocation, as seen from the VM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EcalRecord {
+    pub number: u64,
+    pub name: String,
+    // The argument words, read according to the function's `ArgLayout`.
+    pub args: Vec<u64>,
+    // The sha256 of the memory the arguments point to, see `ArgData`.
+    pub data: Vec<u8>,
+    // rB after the call.
+    pub rb: u64,
+    // HP after the call, and the heap memory the call allocated and wrote,
+    // i.e. everything from the new HP up to the old one.
+    pub hp: u64,
+    pub heap: Vec<u8>,
+    pub gas_used: u64,
+    pub error: Option<String>,
+    // For errors that panicked the VM with a reason other than `EcalError`,
+    // e.g. `OutOfGas`, the reason, so replay panics the same way.
+    pub panic: Option<u8>,
+}
+
+// A script run in a recording: the script, its input, and its ECALs in order.
+#[derive(Debug, Clone)]
+pub struct RecordedRun {
+    pub script_path: String,
+    pub script_data: Vec<u8>,
+    pub ecals: Vec<EcalRecord>,
+}
+
+// Recordings are JSON lines: a `run` line at the start of every script run,
+// followed by one `ecal` line per invocation.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    path: PathBuf,
+    file: Arc<Mutex<File>>,
+}
+
+impl Recorder {
+    // Appends to `path`, so consecutive runs end up in one recording.
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .context(format!("{path:?}"))?;
+        Ok(Self {
+            path,
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    pub fn start_run(&self, script_path: &str, script_data: &[u8]) -> anyhow::Result<()> {
+        let line = json!({
+            "run": script_path,
+            "script_data": hex::encode(script_data),
+        });
+        self.write_line(&line).context(format!("{:?}", self.path))
+    }
+
+    pub fn record<S, Tx>(
+        &self,
+        vm: &mut Interpreter<S, Tx, MyEcal>,
+        function: &EcalFunction,
+        rb: RegId,
+    ) -> EcalResult<()> {
+        let args = read_args(vm, rb, function.args)?;
+        let data = data_hash(vm, function, &args)?;
+        let hp = vm.registers()[RegId::HP];
+        let gas = vm.registers()[RegId::GGAS];
+
+        let result = function.handler.call(vm, rb);
+
+        let new_hp = vm.registers()[RegId::HP];
+        let heap = {
+            let r = MemoryRange::new(new_hp, hp.saturating_sub(new_hp))?;
+            vm.memory()[r.usizes()].to_vec()
+        };
+        let record = EcalRecord {
+            number: function.number,
+            name: function.name.to_string(),
+            args,
+            data,
+            rb: vm.registers()[rb],
+            hp: new_hp,
+            heap,
+            gas_used: gas.saturating_sub(vm.registers()[RegId::GGAS]),
+            error: result.as_ref().err().map(ToString::to_string),
+            panic: match &result {
+                Err(EcalError::Vm(PanicOrBug::Panic(reason))) => Some(*reason as u8),
+                _ => None,
+            },
+        };
+        self.write_line(&record.to_json())
+            .map_err(|source| EcalError::Io {
+                path: self.path.display().to_string(),
+                source,
+            })?;
+        result
+    }
+
+    fn write_line(&self, line: &serde_json::Value) -> std::io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{line}")?;
+        file.flush()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Replayer {
+    ecals: Arc<Mutex<VecDeque<EcalRecord>>>,
+}
+
+impl Replayer {
+    pub fn new(ecals: Vec<EcalRecord>) -> Self {
+        Self {
+            ecals: Arc::new(Mutex::new(ecals.into())),
+        }
+    }
+
+    // Checks that the script makes the recorded call, then either serves the
+    // recorded result or, for functions that only depend on VM memory, runs
+    // the handler again.
+    pub fn replay<S, Tx>(
+        &self,
+        vm: &mut Interpreter<S, Tx, MyEcal>,
+        function: &EcalFunction,
+        rb: RegId,
+    ) -> EcalResult<()> {
+        let args = read_args(vm, rb, function.args)?;
+        let record = self.ecals.lock().unwrap().pop_front().ok_or_else(|| {
+            EcalError::Replay(format!(
+                "{}({args:?}) was not recorded, the recording has ended",
+                function.name
+            ))
+        })?;
+        if record.number != function.number || record.args != args {
+            return Err(EcalError::Replay(format!(
+                "expected {}({:?}) but the script called {}({args:?})",
+                record.name, record.args, function.name
+            )));
+        }
+        if record.data != data_hash(vm, function, &args)? {
+            return Err(EcalError::Replay(format!(
+                "{}({args:?}) was called with other values than recorded",
+                function.name
+            )));
+        }
+
+        if function.replay == Replay::Rerun {
+            return function.handler.call(vm, rb);
+        }
+
+        vm.gas_charge(record.gas_used)?;
+        if let Some(reason) = record.panic {
+            return Err(PanicReason::from(reason).into());
+        }
+        if let Some(error) = record.error {
+            return Err(EcalError::Replay(format!("recorded failure: {error}")));
+        }
+        if !record.heap.is_empty() {
+            vm.allocate(record.heap.len() as u64)?;
+            let hp = vm.registers()[RegId::HP];
+            if hp != record.hp {
+                return Err(EcalError::Replay(format!(
+                    "heap pointer is {hp}, but it was {} when recorded",
+                    record.hp
+                )));
+            }
+            let r = MemoryRange::new(hp, record.heap.len())?;
+            vm.memory_mut()[r.usizes()].copy_from_slice(&record.heap);
+        }
+        vm.registers_mut()[rb] = record.rb;
+        Ok(())
+    }
+}
+
+pub fn read_recording(path: impl AsRef<Path>) -> anyhow::Result<Vec<RecordedRun>> {
+    let path = path.as_ref();
+    let file = File::open(path).context(format!("{path:?}"))?;
+    let mut runs: Vec<RecordedRun> = vec![];
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let context = || format!("{path:?}, line {}", n + 1);
+        let value: serde_json::Value = serde_json::from_str(&line).with_context(context)?;
+        if let Some(script_path) = value.get("run").and_then(|v| v.as_str()) {
+            let script_data = value
+                .get("script_data")
+                .and_then(|v| v.as_str())
+                .map(hex::decode)
+                .transpose()?
+                .unwrap_or_default();
+            runs.push(RecordedRun {
+                script_path: script_path.to_string(),
+                script_data,
+                ecals: vec![],
+            });
+        } else {
+            let record = EcalRecord::from_json(&value).with_context(context)?;
+            runs.last_mut()
+                .with_context(|| format!("{}: ECAL before the first run", context()))?
+                .ecals
+                .push(record);
+        }
+    }
+    Ok(runs)
+}
+
+impl EcalRecord {
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "ecal": self.number,
+            "name": self.name,
+            "args": self.args,
+            "data": hex::encode(&self.data),
+            "rb": self.rb,
+            "hp": self.hp,
+            "heap": hex::encode(&self.heap),
+            "gas_used": self.gas_used,
+            "error": self.error,
+            "panic": self.panic,
+        })
+    }
+
+    fn from_json(value: &serde_json::Value) -> anyhow::Result<Self> {
+        let u64_field = |name: &str| {
+            value
+                .get(name)
+                .and_then(|v| v.as_u64())
+                .with_context(|| format!("missing '{name}'"))
+        };
+        let args = value
+            .get("args")
+            .and_then(|v| v.as_array())
+            .context("missing 'args'")?
+            .iter()
+            .map(|v| v.as_u64().context("'args' must be u64 words"))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            number: u64_field("ecal")?,
+            name: value
+                .get("name")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string(),
+            args,
+            data: hex::decode(
+                value
+                    .get("data")
+                    .and_then(|v| v.as_str())
+                    .context("missing 'data'")?,
+            )?,
+            rb: u64_field("rb")?,
+            hp: u64_field("hp")?,
+            heap: hex::decode(
+                value
+                    .get("heap")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default(),
+            )?,
+            gas_used: u64_field("gas_used")?,
+            error: value
+                .get("error")
+                .and_then(|v| v.as_str())
+                .map(str::to_string),
+            panic: value
+                .get("panic")
+                .and_then(|v| v.as_u64())
+                .map(|reason| reason as u8),
+        })
+    }
+}
+
+fn read_args<S, Tx>(
+    vm: &Interpreter<S, Tx, MyEcal>,
+    rb: RegId,
+    layout: ArgLayout,
+) -> EcalResult<Vec<u64>> {
+    match layout {
+        ArgLayout::Register(_) => Ok(vec![vm.registers()[rb]]),
+        ArgLayout::Tuple(names) => super::read_u64s(vm, vm.registers()[rb], names.len()),
+    }
+}
+
+// The sha256 of the memory the arguments of `function` point to, each region
+// preceded by its length.
+fn data_hash<S, Tx>(
+    vm: &Interpreter<S, Tx, MyEcal>,
+    function: &EcalFunction,
+    args: &[u64],
+) -> EcalResult<Vec<u8>> {
+    // The names are checked by `EcalRegistry::register`.
+    let arg = |name: &str| match function.args {
+        ArgLayout::Tuple(names) => args[names.iter().position(|n| *n == name).unwrap()],
+        ArgLayout::Register(_) => unreachable!(),
+    };
+    let mut hasher = Sha256::new();
+    for data in function.data {
+        let len = data
+            .len
+            .iter()
+            .fold(1u64, |len, name| len.saturating_mul(arg(name)));
+        hasher.update(len.to_be_bytes());
+        hasher.update(super::read_memory(vm, arg(data.ptr), len)?);
+    }
+    Ok(hasher.finalize().to_vec())
+}
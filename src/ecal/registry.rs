@@ -0,0 +1,322 @@
+use fuel_asm::RegId;
+use fuel_vm::prelude::Interpreter;
+
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use super::{
+    ecal_delete, ecal_load, ecal_print, ecal_read_file, ecal_save, ecal_type_id, EcalResult, MyEcal,
+};
+
+pub type EcalFn<S, Tx> = fn(&mut Interpreter<S, Tx, MyEcal>, RegId) -> EcalResult<()>;
+
+// How the arguments of an ECAL are passed in the rB register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgLayout {
+    // rB holds the argument itself, e.g. `println_u64`.
+    Register(&'static str),
+    // rB holds the address of a tuple of u64 words, e.g. `(type_id, addr, size)`.
+    Tuple(&'static [&'static str]),
+}
+
+impl std::fmt::Display for ArgLayout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArgLayout::Register(name) => write!(f, "rB = {name}"),
+            ArgLayout::Tuple(names) => write!(f, "rB = &({})", names.join(", ")),
+        }
+    }
+}
+
+// Memory an ECAL reads: at the argument `ptr`, as many bytes as the product
+// of the `len` arguments. Recordings keep a hash of it, so replay can tell
+// a script passing other values at the same addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArgData {
+    pub ptr: &'static str,
+    pub len: &'static [&'static str],
+}
+
+// What replaying a recording does with calls to a function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replay {
+    // Serve the recorded result. For functions using the database or files.
+    Recorded,
+    // Run the handler again. For functions that only depend on VM memory and
+    // the ABI, e.g. printing.
+    Rerun,
+}
+
+// The handler of a function. The registry is built once, see
+// `host_functions`, so it can't hold handlers generic over the storage and
+// transaction types of the VM. `call` picks the handler instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Handler {
+    ReadFile,
+    PrintlnStr,
+    PrintlnU64,
+    Save,
+    Load,
+    Delete,
+    SaveMany,
+    TypeId,
+    PrintAny,
+    LoadMany,
+}
+
+impl Handler {
+    pub fn call<S, Tx>(self, vm: &mut Interpreter<S, Tx, MyEcal>, rb: RegId) -> EcalResult<()> {
+        let handler: EcalFn<S, Tx> = match self {
+            Handler::ReadFile => ecal_read_file::read_file,
+            Handler::PrintlnStr => ecal_print::println_str,
+            Handler::PrintlnU64 => ecal_print::println_u64,
+            Handler::Save => ecal_save::save,
+            Handler::Load => ecal_load::load,
+            Handler::Delete => ecal_delete::delete,
+            Handler::SaveMany => ecal_save::save_many,
+            Handler::TypeId => ecal_type_id::type_id,
+            Handler::PrintAny => ecal_print::println,
+            Handler::LoadMany => ecal_load::load_many,
+        };
+        handler(vm, rb)
+    }
+}
+
+#[derive(Debug)]
+pub struct EcalFunction {
+    pub number: u64,
+    pub name: &'static str,
+    pub args: ArgLayout,
+    pub data: &'static [ArgData],
+    pub replay: Replay,
+    pub handler: Handler,
+}
+
+// Host functions callable from Sway with `ecal rA rB rC rD`, keyed by the
+// function number passed in rA.
+#[derive(Debug, Default)]
+pub struct EcalRegistry {
+    functions: BTreeMap<u64, EcalFunction>,
+}
+
+impl EcalRegistry {
+    pub fn new() -> Self {
+        Self {
+            functions: BTreeMap::new(),
+        }
+    }
+
+    // Panics if either the number or the name is already taken, as that is a
+    // programming error in the host, not in the script.
+    pub fn register(
+        &mut self,
+        number: u64,
+        name: &'static str,
+        args: ArgLayout,
+        data: &'static [ArgData],
+        replay: Replay,
+        handler: Handler,
+    ) -> &mut Self {
+        if let Some(existing) = self.functions.get(&number) {
+            panic!(
+                "ECAL number {number} is already registered for '{}', can't register '{name}'",
+                existing.name
+            );
+        }
+        if let Some(existing) = self.by_name(name) {
+            panic!(
+                "ECAL '{name}' is already registered with number {}",
+                existing.number
+            );
+        }
+        let names = match args {
+            ArgLayout::Register(_) => &[][..],
+            ArgLayout::Tuple(names) => names,
+        };
+        for arg in data
+            .iter()
+            .flat_map(|data| data.len.iter().chain([&data.ptr]))
+        {
+            if !names.contains(arg) {
+                panic!("ECAL '{name}' has no argument '{arg}'");
+            }
+        }
+        self.functions.insert(
+            number,
+            EcalFunction {
+                number,
+                name,
+                args,
+                data,
+                replay,
+                handler,
+            },
+        );
+        self
+    }
+
+    pub fn get(&self, number: u64) -> Option<&EcalFunction> {
+        self.functions.get(&number)
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&EcalFunction> {
+        self.functions.values().find(|f| f.name == name)
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item = &EcalFunction> {
+        self.functions.values()
+    }
+
+    // Sway source for `ecal-lib`'s `numbers` module, so the numbers used in
+    // the `asm` blocks come from the same table as the host handlers.
+    pub fn sway_numbers(&self) -> String {
+        let mut result = vec![
+            "library;".to_string(),
+            "".to_string(),
+            "// Generated by `cargo run -- ecal-numbers`. Do not edit by hand.".to_string(),
+        ];
+        for f in self.functions() {
+            result.push("".to_string());
+            result.push(format!("// {}", f.args));
+            result.push(format!(
+                "pub const {}: u64 = {};",
+                f.name.to_uppercase(),
+                f.number
+            ));
+        }
+        result.join("\n") + "\n"
+    }
+}
+
+// The host functions available to indexer scripts, built on first use. To
+// add one, write the handler, add it to `Handler`, register it here, and
+// regenerate ecal-lib's `numbers.sw`.
+pub fn host_functions() -> &'static EcalRegistry {
+    static REGISTRY: OnceLock<EcalRegistry> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let mut registry = EcalRegistry::new();
+        registry
+            .register(
+                0,
+                "read_file",
+                ArgLayout::Tuple(&["seek", "len", "path_ptr", "path_len"]),
+                &[ArgData {
+                    ptr: "path_ptr",
+                    len: &["path_len"],
+                }],
+                Replay::Recorded,
+                Handler::ReadFile,
+            )
+            .register(
+                1,
+                "println",
+                ArgLayout::Tuple(&["str_ptr", "str_len"]),
+                &[ArgData {
+                    ptr: "str_ptr",
+                    len: &["str_len"],
+                }],
+                Replay::Rerun,
+                Handler::PrintlnStr,
+            )
+            .register(
+                2,
+                "println_u64",
+                ArgLayout::Register("value"),
+                &[],
+                Replay::Rerun,
+                Handler::PrintlnU64,
+            )
+            .register(
+                3,
+                "save",
+                ArgLayout::Tuple(&["type_id", "addr", "size", "return_id"]),
+                &[ArgData {
+                    ptr: "addr",
+                    len: &["size"],
+                }],
+                Replay::Recorded,
+                Handler::Save,
+            )
+            .register(
+                4,
+                "load",
+                ArgLayout::Tuple(&["type_id", "filter_ptr", "filter_len"]),
+                &[ArgData {
+                    ptr: "filter_ptr",
+                    len: &["filter_len"],
+                }],
+                Replay::Recorded,
+                Handler::Load,
+            )
+            .register(
+                5,
+                "delete",
+                ArgLayout::Tuple(&["type_id", "filter_ptr", "filter_len"]),
+                &[ArgData {
+                    ptr: "filter_ptr",
+                    len: &["filter_len"],
+                }],
+                Replay::Recorded,
+                Handler::Delete,
+            )
+            .register(
+                6,
+                "save_many",
+                ArgLayout::Tuple(&["type_id", "addr", "count", "elt_size"]),
+                &[ArgData {
+                    ptr: "addr",
+                    len: &["count", "elt_size"],
+                }],
+                Replay::Recorded,
+                Handler::SaveMany,
+            )
+            .register(
+                7,
+                "type_id",
+                ArgLayout::Tuple(&["type_name_ptr", "type_name_len"]),
+                &[ArgData {
+                    ptr: "type_name_ptr",
+                    len: &["type_name_len"],
+                }],
+                Replay::Rerun,
+                Handler::TypeId,
+            )
+            .register(
+                8,
+                "print_any",
+                ArgLayout::Tuple(&["type_id", "addr", "size"]),
+                &[ArgData {
+                    ptr: "addr",
+                    len: &["size"],
+                }],
+                Replay::Rerun,
+                Handler::PrintAny,
+            )
+            .register(
+                9,
+                "load_many",
+                ArgLayout::Tuple(&[
+                    "type_id",
+                    "filter_ptr",
+                    "filter_len",
+                    "order_ptr",
+                    "order_len",
+                    "limit",
+                    "offset",
+                ]),
+                &[
+                    ArgData {
+                        ptr: "filter_ptr",
+                        len: &["filter_len"],
+                    },
+                    ArgData {
+                        ptr: "order_ptr",
+                        len: &["order_len"],
+                    },
+                ],
+                Replay::Recorded,
+                Handler::LoadMany,
+            );
+        registry
+    })
+}
@@ -0,0 +1,97 @@
+use fuels::types::Token;
+
+use std::sync::{Arc, Mutex};
+
+use super::ecal_save::{fetch_id, RowRef, SaveStmtBuilder};
+use super::{EcalError, EcalResult};
+use crate::db::Db;
+
+// Collects the statements generated by `save` for a whole script run, so they
+// go to the database in one round trip instead of one per call. The buffer is
+// flushed before every `load`, so reads see the buffered writes, and by the
+// host at the end of the run. Shared between the clones of `MyEcal`.
+#[derive(Debug, Clone, Default)]
+pub struct WriteBuffer(Arc<Mutex<Option<SaveStmtBuilder>>>);
+
+// Postgres allows at most 65535 parameters in a statement. Flushing at half
+// of that leaves room for the next value.
+pub(super) const MAX_ARGS: usize = 32_768;
+
+impl WriteBuffer {
+    // Whether the buffer should be flushed before taking more values.
+    pub(super) fn is_full(&self) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|builder| builder.args.len() >= MAX_ARGS)
+    }
+
+    // Whether saving the value would change a keyed entity the buffer
+    // already saves, see `EntityKeys`. `probe` is an empty builder.
+    pub(super) fn updates_buffered(
+        &self,
+        mut probe: SaveStmtBuilder,
+        type_id: usize,
+        value: &Token,
+    ) -> EcalResult<bool> {
+        let builder = self.0.lock().unwrap();
+        let Some(builder) = builder.as_ref() else {
+            return Ok(false);
+        };
+        probe.save_value(type_id, value.clone())?;
+        Ok(builder.conflicts_with(&probe))
+    }
+
+    // Returns the number of statements and rows the value added, and its row.
+    // Values, or parts of them, that are already in the buffer don't add any
+    // rows, and tables already in it no statements. `new_builder` creates the
+    // buffer when it's empty.
+    pub(super) fn push(
+        &self,
+        new_builder: impl FnOnce() -> SaveStmtBuilder,
+        type_id: usize,
+        value: Token,
+    ) -> EcalResult<(usize, usize, Option<RowRef>)> {
+        let mut builder = self.0.lock().unwrap();
+        let builder = builder.get_or_insert_with(new_builder);
+        let (statements, rows) = (builder.statements(), builder.rows());
+        let row = builder.save_value(type_id, value)?;
+        Ok((
+            builder.statements() - statements,
+            builder.rows() - rows,
+            row,
+        ))
+    }
+
+    // Runs the buffered statements as one statement and empties the buffer,
+    // also if the statement fails. Returns the number of statements flushed.
+    pub fn flush(&self, db: &Db) -> EcalResult<usize> {
+        self.flush_with(db, None).map(|(flushed, _)| flushed)
+    }
+
+    // Like `flush`, but also returns the id of the `row`, which must be in
+    // the buffer.
+    pub(super) fn flush_returning(&self, db: &Db, row: Option<&RowRef>) -> EcalResult<u64> {
+        self.flush_with(db, row).map(|(_, id)| id)
+    }
+
+    fn flush_with(&self, db: &Db, row: Option<&RowRef>) -> EcalResult<(usize, u64)> {
+        let Some(builder) = self.0.lock().unwrap().take() else {
+            return Ok((0, 0));
+        };
+        if builder.statements() == 0 {
+            return Ok((0, 0));
+        }
+        let stmt = builder.stmt(row);
+        let start = std::time::Instant::now();
+        let id = fetch_id(db, &stmt, &builder.args, row)
+            .map_err(|source| EcalError::Flush { sql: stmt, source })?;
+        println!(
+            "ECAL::flush: {:?}, {} statements",
+            start.elapsed(),
+            builder.statements()
+        );
+        Ok((builder.statements(), id))
+    }
+}
@@ -4,12 +4,17 @@ use fuels::types::{enum_variants::EnumVariants, Token};
 pub trait TypeDeclarationExt {
     fn struct_or_enum_name(&self) -> Option<String>;
     fn decl_fields(&self, abi: &crate::ABI) -> Vec<TypeDeclaration>;
+    fn is_u128(&self) -> bool;
     fn is_u256(&self) -> bool;
+    fn is_std_scalar(&self) -> bool;
+    fn is_tuple(&self) -> bool;
     fn is_enum(&self) -> bool;
     fn is_struct(&self) -> bool;
     fn is_array(&self) -> bool;
     fn is_u8(&self) -> bool;
     fn is_option(&self) -> bool;
+    fn is_byte_array(&self, abi: &crate::ABI) -> bool;
+    fn is_fieldless_enum(&self, abi: &crate::ABI) -> bool;
     fn has_nested_struct(&self, abi: &crate::ABI) -> bool;
     fn has_nested_enum(&self, abi: &crate::ABI) -> bool;
     fn has_nested_array(&self, abi: &crate::ABI) -> bool;
@@ -33,14 +38,37 @@ impl TypeDeclarationExt for TypeDeclaration {
         result
     }
 
+    fn is_u128(&self) -> bool {
+        self.type_field.starts_with("struct U128")
+    }
+
     fn is_u256(&self) -> bool {
         self.type_field.starts_with("struct U256")
     }
 
+    // Structs the SDK decodes as scalars, e.g. `String` to `Token::String`.
+    // They are stored in columns, not in tables of their own.
+    fn is_std_scalar(&self) -> bool {
+        self.is_u128()
+            || self.is_u256()
+            || matches!(
+                self.type_field.as_str(),
+                "struct String"
+                    | "struct Bytes"
+                    | "struct RawBytes"
+                    | "struct Vec"
+                    | "struct RawVec"
+            )
+    }
+
     fn is_struct(&self) -> bool {
         self.type_field.starts_with("struct")
     }
 
+    fn is_tuple(&self) -> bool {
+        self.type_field.starts_with('(') && self.type_field != "()"
+    }
+
     fn is_enum(&self) -> bool {
         self.type_field.starts_with("enum")
     }
@@ -60,6 +88,34 @@ impl TypeDeclarationExt for TypeDeclaration {
         self.type_field.starts_with("enum Option")
     }
 
+    // `[Option<u8>; N]`, the stand-in for `Vec<u8>` in indexed types. Stored
+    // as a single bytea column holding the `Some` prefix.
+    fn is_byte_array(&self, abi: &crate::ABI) -> bool {
+        let Some(elt) = self.components.as_ref().and_then(|cs| cs.first()) else {
+            return false;
+        };
+        self.is_array()
+            && abi.type_declaration(elt.type_id).is_option()
+            && elt
+                .type_arguments
+                .as_ref()
+                .and_then(|args| args.first())
+                .is_some_and(|arg| abi.type_declaration(arg.type_id).is_u8())
+    }
+
+    // An enum whose variants are all `()`, e.g. `enum Color { Red: (), Blue: () }`.
+    // Stored as a Postgres ENUM of the variant names, not in a table.
+    fn is_fieldless_enum(&self, abi: &crate::ABI) -> bool {
+        self.is_enum()
+            && self.type_parameters.is_none()
+            && self.components.as_ref().is_some_and(|variants| {
+                !variants.is_empty()
+                    && variants
+                        .iter()
+                        .all(|variant| abi.type_declaration(variant.type_id).type_field == "()")
+            })
+    }
+
     fn has_nested_struct(&self, abi: &crate::ABI) -> bool {
         self.decl_fields(abi)
             .iter()
@@ -17,7 +17,7 @@ pub fn schema_from_abi(types: &BTreeMap<usize, TypeDeclaration>) -> String {
     } in types.values()
     {
         if let Some(name) = type_field.strip_prefix("struct ") {
-            if name == "U256" {
+            if matches!(name, "U128" | "U256") {
                 continue;
             }
             println!(">> PROCESSING STRUCT: {name}");
@@ -40,6 +40,7 @@ pub fn schema_from_abi(types: &BTreeMap<usize, TypeDeclaration>) -> String {
                     "u32" => model.push_field(Field::new(name, "Int")),
                     "u64" => model.push_field(Field::new(name, "Int")),
                     "b256" => model.push_field(Field::new(name, "String")),
+                    "struct U128" => model.push_field(Field::new(name, "String")),
                     "struct U256" => model.push_field(Field::new(name, "String")),
                     struct_field if struct_field.starts_with("struct ") => {
                         // field_name StructType @relation(fields: ["struct_nameId"], references: ["id"])
@@ -1,6 +1,6 @@
 use case::CaseExt;
 use sqlparser::ast::ColumnDef;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use fuel_abi_types::abi::program::{TypeApplication, TypeDeclaration};
 use fuels::types::param_types::ParamType;
@@ -12,8 +12,8 @@ use crate::ABI;
 mod sql {
     pub use sqlparser::ast::helpers::stmt_create_table::CreateTableBuilder;
     pub use sqlparser::ast::{
-        ColumnDef, ColumnOption, ColumnOptionDef, DataType, ExactNumberInfo, Ident, ObjectName,
-        Statement, TableConstraint,
+        BinaryOperator, ColumnDef, ColumnOption, ColumnOptionDef, DataType, ExactNumberInfo, Expr,
+        Ident, ObjectName, TableConstraint, Value,
     };
 
     pub fn quoted_ident(name: &str) -> Ident {
@@ -29,89 +29,325 @@ mod sql {
     // }
 }
 
+// How the `id` primary keys of entity tables are generated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PrimaryKeys {
+    // `SERIAL`, in insertion order.
+    #[default]
+    Serial,
+    // `BIGINT`, a hash of the type name and the ABI encoding of the value,
+    // so the same data gets the same ids on every machine.
+    ContentHash,
+}
+
+// The sha256 of the type name and the ABI encoding of the value, unique in
+// each table. With `PrimaryKeys::ContentHash` the id is a prefix of it, two
+// values whose ids collide can't both be saved.
+pub const CONTENT_HASH_COLUMN: &str = "content_hash";
+
+// Key fields of entities that are updated in place: saving a value whose key
+// is already in the table overwrites that row instead of adding another one.
+// The key field must be a scalar field, its column is unique.
+#[derive(Debug, Clone, Default)]
+pub struct EntityKeys(BTreeMap<String, String>);
+
+impl EntityKeys {
+    // Parses `Struct.field` pairs separated by commas, e.g.
+    // `Account.owner,Balance.asset_id`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut keys = BTreeMap::new();
+        for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (struct_name, field) = pair
+                .split_once('.')
+                .ok_or_else(|| format!("expected 'Struct.field', got '{pair}'"))?;
+            if keys
+                .insert(struct_name.to_string(), field.to_string())
+                .is_some()
+            {
+                return Err(format!("more than one key field for '{struct_name}'"));
+            }
+        }
+        Ok(Self(keys))
+    }
+
+    // Checks that every key field is a field of a struct of `abi`, with a
+    // column of its own to make unique.
+    pub fn validate(&self, abi: &ABI) -> Result<(), String> {
+        for (struct_name, key_field) in &self.0 {
+            let decl = abi
+                .type_ids
+                .get(&format!("struct {struct_name}"))
+                .and_then(|type_id| abi.types.get(type_id))
+                .ok_or_else(|| format!("there is no struct '{struct_name}'"))?;
+            let field = decl
+                .components
+                .iter()
+                .flatten()
+                .find(|field| field.name == *key_field)
+                .ok_or_else(|| format!("'{struct_name}' has no field '{key_field}'"))?;
+            let field_decl = abi.type_declaration(field.type_id);
+            let is_scalar = if field_decl.is_struct() || field_decl.is_enum() {
+                field_decl.is_std_scalar() || field_decl.is_fieldless_enum(abi)
+            } else {
+                !field_decl.is_array() && !field_decl.is_tuple() && field_decl.type_field != "()"
+            };
+            if !is_scalar {
+                return Err(format!(
+                    "the key field '{key_field}' of '{struct_name}' must be a scalar field"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // The key field of `struct_name`, if it has one.
+    pub fn get(&self, struct_name: &str) -> Option<&str> {
+        self.0.get(struct_name).map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+// An array field `[Option<T>; N]` is stored in a table named after the field,
+// with a row for every `Some` element. Arrays of the same name in other
+// structs share the table, so their elements must be of the same type.
+pub fn array_table_name(field_name: &str) -> String {
+    field_name.to_capitalized()
+}
+
+// The column of an array table holding the id of the struct with the array.
+pub fn array_parent_column(struct_name: &str) -> String {
+    format!("{}_id", struct_name.to_snake())
+}
+
+// The name of the active variant of a row of an enum table.
+pub const ENUM_VARIANT_COLUMN: &str = "variant";
+
+// The Postgres ENUM type of a fieldless enum, see
+// `TypeDeclarationExt::is_fieldless_enum`. Values are the variant names.
+pub fn fieldless_enum_type(enum_name: &str) -> String {
+    format!("\"{enum_name}\"")
+}
+
+// `Option<T>` of a value stored in a column, e.g. `Option<Bytes>` or an
+// option of a fieldless enum, is stored in the column of `T`, NULL for
+// `None`. Returns the type of `Some`. Options of structs and enums are stored
+// as the id of their row instead, see `SQLTableBuilder::referenced_decl`.
+pub fn column_option(abi: &ABI, field: &TypeApplication) -> Option<TypeApplication> {
+    if !abi.types.get(&field.type_id)?.is_option() {
+        return None;
+    }
+    let some = field.type_arguments.as_ref()?.first()?;
+    let decl = abi.types.get(&some.type_id)?;
+    let in_column = decl.is_fieldless_enum(abi)
+        || (decl.is_std_scalar()
+            && !matches!(
+                decl.type_field.as_str(),
+                "struct RawBytes" | "struct RawVec"
+            ));
+    in_column.then(|| some.clone())
+}
+
+// A tuple field gets a column for every element, `pair: (u64, bool)` gets
+// `pair_0` and `pair_1`.
+pub fn tuple_element_column(field_name: &str, i: usize) -> String {
+    format!("{field_name}_{i}")
+}
+
 pub struct SQLTableBuilder {
+    // CREATE TYPE statements, run before the tables using the types.
+    types: Vec<String>,
     builders: Vec<sql::CreateTableBuilder>,
     extra_builders: Vec<sql::CreateTableBuilder>,
     tables: std::collections::HashSet<String>,
+    // The struct and element type of the first array stored in each array
+    // table, see `array_table_name`.
+    array_tables: std::collections::HashMap<String, (String, Option<String>)>,
+    primary_keys: PrimaryKeys,
+    entity_keys: EntityKeys,
     abi: ABI,
 }
 
 impl SQLTableBuilder {
     pub fn new(abi: ABI) -> Self {
         Self {
+            types: vec![],
             builders: vec![],
             extra_builders: vec![],
             tables: std::collections::HashSet::new(),
+            array_tables: std::collections::HashMap::new(),
+            primary_keys: PrimaryKeys::default(),
+            entity_keys: EntityKeys::default(),
             abi,
         }
     }
 
-    pub fn statements(self) -> Vec<sql::Statement> {
-        let mut result = vec![];
+    pub fn primary_keys(mut self, primary_keys: PrimaryKeys) -> Self {
+        self.primary_keys = primary_keys;
+        self
+    }
+
+    pub fn entity_keys(mut self, entity_keys: EntityKeys) -> Self {
+        self.entity_keys = entity_keys;
+        self
+    }
+
+    pub fn statements(self) -> Vec<String> {
+        let mut result = self.types;
         for b in self.builders {
             let stmt = b.build();
-            result.push(stmt);
+            result.push(stmt.to_string());
         }
         for b in self.extra_builders {
             let stmt = b.build();
-            result.push(stmt);
+            result.push(stmt.to_string());
         }
         result
     }
 
-    pub fn process_program_abi(&mut self, abi: &ABI) {
+    // Fails on types that can't be stored, naming the field.
+    pub fn process_program_abi(&mut self, abi: &ABI) -> Result<(), String> {
         let fuel_block_decl = self
             .abi
             .types
             .get(abi.type_ids.get("struct FuelBlock").unwrap())
             .unwrap();
 
-        self.process_decl(fuel_block_decl.clone());
+        self.process_decl(fuel_block_decl.clone())?;
 
         for decl in abi.types.values() {
             println!("PROCESS DECL: {}", decl.type_field);
-            self.process_decl(decl.clone())
+            self.process_decl(decl.clone())?;
         }
+        Ok(())
     }
 
-    pub fn process_decl(&mut self, decl: TypeDeclaration) {
+    pub fn process_decl(&mut self, decl: TypeDeclaration) -> Result<(), String> {
         if let Some(struct_name) = decl.type_field.strip_prefix("struct ") {
             self.process_struct(struct_name, decl.components.as_ref().unwrap())
         } else if let Some(enum_name) = decl.type_field.strip_prefix("enum ") {
             self.process_enum(enum_name)
+        } else {
+            Ok(())
         }
     }
 
-    // ???
+    // A table with the name of the active variant, and the columns of the
+    // values of all variants, as if they were the fields of a struct. Only
+    // the columns of the active variant are set:
+    //
     // enum Transaction {
-    //     Mint(Mint),
-    //     Create(Create),
+    //     Script: Script,
+    //     Mint: Mint,
     // }
-    fn process_enum(&mut self, enum_name: &str) {
+    //
+    // CREATE TABLE "Transaction" (
+    //     id SERIAL PRIMARY KEY, content_hash BYTEA NOT NULL UNIQUE,
+    //     variant TEXT NOT NULL,
+    //     "ScriptId" BIGINT, "MintId" BIGINT,
+    //     FOREIGN KEY ("ScriptId") REFERENCES "Script"(id) ON DELETE RESTRICT,
+    //     FOREIGN KEY ("MintId") REFERENCES "Mint"(id) ON DELETE RESTRICT,
+    //     CONSTRAINT one_variant CHECK (variant IN ('Script', 'Mint')
+    //         AND (variant = 'Script') = ("ScriptId" IS NOT NULL)
+    //         AND (variant = 'Mint') = ("MintId" IS NOT NULL))
+    // )
+    fn process_enum(&mut self, enum_name: &str) -> Result<(), String> {
         let type_id = self.abi.type_ids.get(&format!("enum {enum_name}")).unwrap();
         let decl = self.abi.types.get(type_id).unwrap().to_owned();
 
         // An enum with generic parameters (e.g. Option<T>). Skip it.
         if decl.type_parameters.is_some() {
-            return;
+            return Ok(());
+        }
+
+        if decl.is_fieldless_enum(&self.abi) {
+            self.process_fieldless_enum(enum_name, &decl);
+            return Ok(());
         }
 
         // Conumns and tables for variants.
         let mut columns = vec![];
 
-        columns.push(Self::pk_column());
+        columns.extend(self.key_columns());
 
-        for c in decl.components.as_ref().unwrap() {
-            let variant_decl = self.abi.types.get(&c.type_id).unwrap();
-            self.process_decl(variant_decl.clone());
+        columns.push(ColumnDef {
+            name: sql::Ident::new(ENUM_VARIANT_COLUMN),
+            data_type: sql::DataType::Text,
+            collation: None,
+            options: vec![sql::ColumnOptionDef {
+                name: None,
+                option: sql::ColumnOption::NotNull,
+            }],
+        });
 
-            columns.push(ColumnDef {
-                name: sql::quoted_ident(&format!("{}Id", c.name)),
-                data_type: sql::DataType::Int(None),
-                collation: None,
-                options: vec![],
-            });
+        let type_lookup = HashMap::from_iter(self.abi.types.clone());
+        let variant = || Box::new(sql::Expr::Identifier(sql::Ident::new(ENUM_VARIANT_COLUMN)));
+        let variant_name =
+            |name: &str| sql::Expr::Value(sql::Value::SingleQuotedString(name.to_string()));
+        let mut constraints = vec![];
+        let mut variant_names = vec![];
+        let mut checks = vec![];
+        for c in decl.components.as_ref().unwrap() {
+            let variant_decl = self.abi.types.get(&c.type_id).unwrap().clone();
+            self.process_decl(variant_decl.clone())?;
+            variant_names.push(variant_name(&c.name));
+
+            // Arrays of entities need a parent struct, see `process_param_type`.
+            if variant_decl.is_array() && !variant_decl.is_byte_array(&self.abi) {
+                return Err(format!(
+                    "`{enum_name}::{}`, arrays of entities are only supported in structs",
+                    c.name
+                ));
+            }
+            let param_type = ParamType::try_from_type_application(c, &type_lookup)
+                .map_err(|e| format!("`{enum_name}::{}`: {e}", c.name))?;
+            let variant_columns =
+                self.process_param_type(c, Some(enum_name.to_string()), param_type)?;
+
+            // The id of a struct or an enum, or of `Some`, see `process_struct`.
+            if let Some(referenced) = self.referenced_decl(c) {
+                let table = referenced.struct_or_enum_name().unwrap();
+                self.process_decl(referenced)?;
+                let column = sql::Ident::new(format!("\"{}Id\"", c.name));
+                constraints.push(Self::reference_constraint(column, &table));
+            }
+            // `None` is NULL, so an option may be NULL either way.
+            if !variant_decl.is_option() {
+                for column in &variant_columns {
+                    // (variant = 'A') = ("AId" IS NOT NULL)
+                    checks.push(sql::Expr::BinaryOp {
+                        left: Box::new(sql::Expr::Nested(Box::new(sql::Expr::BinaryOp {
+                            left: variant(),
+                            op: sql::BinaryOperator::Eq,
+                            right: Box::new(variant_name(&c.name)),
+                        }))),
+                        op: sql::BinaryOperator::Eq,
+                        right: Box::new(sql::Expr::Nested(Box::new(sql::Expr::IsNotNull(
+                            Box::new(sql::Expr::Identifier(column.name.clone())),
+                        )))),
+                    });
+                }
+            }
+            columns.extend(variant_columns);
         }
+        let one_variant = checks.into_iter().fold(
+            sql::Expr::InList {
+                expr: variant(),
+                list: variant_names,
+                negated: false,
+            },
+            |lhs, rhs| sql::Expr::BinaryOp {
+                left: Box::new(lhs),
+                op: sql::BinaryOperator::And,
+                right: Box::new(rhs),
+            },
+        );
+        constraints.push(sql::TableConstraint::Check {
+            name: Some(sql::Ident::new("one_variant")),
+            expr: Box::new(one_variant),
+        });
 
         // Table for the enum.
         let table_name = sql::ObjectName(vec![sql::Ident::new(format!(
@@ -121,52 +357,105 @@ impl SQLTableBuilder {
 
         let builder = sql::CreateTableBuilder::new(table_name)
             .if_not_exists(true)
-            .columns(columns);
-        self.builders.push(builder)
+            .columns(columns)
+            .constraints(constraints);
+        if !self.tables.contains(&builder.name.to_string()) {
+            self.tables.insert(builder.name.to_string());
+            self.builders.push(builder)
+        }
+        Ok(())
     }
 
-    fn process_struct(&mut self, struct_name: &str, struct_fields: &Vec<TypeApplication>) {
-        if self.tables.contains(struct_name) {
-            println!("SKIPPING {struct_name}");
+    // CREATE TYPE has no IF NOT EXISTS, so the statement ignores the error
+    // when the type exists. Its variants are not updated.
+    fn process_fieldless_enum(&mut self, enum_name: &str, decl: &TypeDeclaration) {
+        if !self.tables.insert(fieldless_enum_type(enum_name)) {
             return;
+        }
+        let variants: Vec<String> = decl
+            .components
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|variant| format!("'{}'", variant.name))
+            .collect();
+        self.types.push(format!(
+            "DO $$ BEGIN CREATE TYPE {} AS ENUM ({}); EXCEPTION WHEN duplicate_object THEN NULL; END $$",
+            fieldless_enum_type(enum_name),
+            variants.join(", ")
+        ));
+    }
+
+    fn process_struct(
+        &mut self,
+        struct_name: &str,
+        struct_fields: &Vec<TypeApplication>,
+    ) -> Result<(), String> {
+        if self.tables.contains(&format!("\"{struct_name}\"")) {
+            println!("SKIPPING {struct_name}");
+            return Ok(());
         };
-        if struct_name == "U256" {
-            return;
+        if matches!(struct_name, "U128" | "U256") {
+            return Ok(());
         }
         if struct_name == "RawVec" {
-            return;
+            return Ok(());
         }
         if struct_name == "Vec" {
-            return;
+            return Ok(());
+        }
+        // Stored in columns, see `TypeDeclarationExt::is_std_scalar`.
+        if matches!(struct_name, "String" | "Bytes" | "RawBytes") {
+            return Ok(());
+        }
+        // The tables the `"{name}Id"` columns point to go first.
+        let mut constraints = vec![];
+        for field in struct_fields {
+            if let Some(decl) = self.referenced_decl(field) {
+                let table = decl.struct_or_enum_name().unwrap();
+                self.process_decl(decl)?;
+                let column = sql::Ident::new(format!("\"{}Id\"", field.name));
+                constraints.push(Self::reference_constraint(column, &table));
+            }
         }
         let type_lookup = HashMap::from_iter(self.abi.types.clone());
-        let mut columns: Vec<sql::ColumnDef> = struct_fields
-            .iter()
-            .map(|type_application| {
-                let param_type =
-                    ParamType::try_from_type_application(&type_application, &type_lookup)
-                        .expect(&format!("{type_application:#?}"));
-                self.process_param_type(
-                    &type_application,
-                    Some(struct_name.to_string()),
-                    param_type,
-                )
-            })
-            .flatten()
-            .collect();
-        columns.push(Self::pk_column());
-        // move 'id' column to the front
-        columns.rotate_right(1);
+        let mut columns: Vec<sql::ColumnDef> = vec![];
+        for type_application in struct_fields {
+            let param_type = ParamType::try_from_type_application(&type_application, &type_lookup)
+                .map_err(|e| format!("`{struct_name}.{}`: {e}", type_application.name))?;
+            columns.extend(self.process_param_type(
+                &type_application,
+                Some(struct_name.to_string()),
+                param_type,
+            )?);
+        }
+        if let Some(key_field) = self.entity_keys.get(struct_name) {
+            let name = sql::Ident::new(format!("\"{key_field}\""));
+            let column = columns.iter_mut().find(|c| c.name == name).ok_or_else(|| {
+                format!("the key field '{key_field}' of '{struct_name}' must be a scalar field")
+            })?;
+            column.options.push(sql::ColumnOptionDef {
+                name: None,
+                option: sql::ColumnOption::Unique { is_primary: false },
+            });
+        }
+        // move the key columns to the front
+        let key_columns = self.key_columns();
+        let n = key_columns.len();
+        columns.extend(key_columns);
+        columns.rotate_right(n);
 
         let table_name = sql::ObjectName(vec![sql::Ident::new(format!("\"{struct_name}\""))]);
         let builder = sql::CreateTableBuilder::new(table_name)
             .if_not_exists(true)
-            .columns(columns);
+            .columns(columns)
+            .constraints(constraints);
 
         if !self.tables.contains(&builder.name.to_string()) {
             self.tables.insert(builder.name.to_string());
             self.builders.push(builder);
         }
+        Ok(())
     }
 
     fn process_param_type(
@@ -174,9 +463,9 @@ impl SQLTableBuilder {
         type_application: &TypeApplication,
         struct_name: Option<String>,
         param_type: ParamType,
-    ) -> Vec<sql::ColumnDef> {
+    ) -> Result<Vec<sql::ColumnDef>, String> {
         let name = &type_application.name;
-        match param_type.clone() {
+        let columns = match param_type.clone() {
             ParamType::Bool => Self::one_column(name, sql::DataType::Boolean),
             // TODO: add constraints
             // CREATE TABLE my_table (
@@ -184,9 +473,9 @@ impl SQLTableBuilder {
             //     -- other columns
             //     CONSTRAINT positive_id CHECK (id >= 0)
             // );
-            ParamType::U8 | ParamType::U16 | ParamType::U32 => {
-                Self::one_column(name, sql::DataType::Integer(None))
-            }
+            ParamType::U8 | ParamType::U16 => Self::one_column(name, sql::DataType::Integer(None)),
+            // INTEGER is signed, so it only holds half of the u32 range.
+            ParamType::U32 => Self::one_column(name, sql::DataType::BigInt(None)),
             // For a 64-bit unsigned integer, which ranges from 0 to
             // 18,446,744,073,709,551,615 (a 20-digit number), we need
             // Precision(20)
@@ -203,43 +492,71 @@ impl SQLTableBuilder {
                 Self::one_column(&format!("{name}Id"), sql::DataType::BigInt(None))
             }
             ParamType::Tuple(elems) => {
+                let decl = self.abi.types.get(&type_application.type_id).unwrap();
+                let elem_applications = decl.components.clone().unwrap_or_default();
                 let mut columns = vec![];
-                for elem in elems.iter() {
-                    let column = self.process_param_type(type_application, None, elem.clone());
-                    columns.push(column);
+                for (i, (elem_application, elem)) in
+                    elem_applications.into_iter().zip(elems).enumerate()
+                {
+                    // Saved and loaded as columns, see `ecal_save::scalar_args`.
+                    if matches!(
+                        elem,
+                        ParamType::Struct { .. } | ParamType::Enum { .. } | ParamType::Array(..)
+                    ) {
+                        return Err(format!(
+                            "`{name}: {param_type:?}`, tuple elements must be scalars"
+                        ));
+                    }
+                    let elem_application = TypeApplication {
+                        name: tuple_element_column(name, i),
+                        ..elem_application
+                    };
+                    columns.extend(self.process_param_type(&elem_application, None, elem)?);
                 }
-                columns.into_iter().flatten().collect()
+                columns
+            }
+            // No value, no column.
+            ParamType::Unit => vec![],
+            ParamType::String | ParamType::StringSlice | ParamType::StringArray(_) => {
+                Self::one_column(name, sql::DataType::Text)
             }
-            ParamType::String => Self::one_column(name, sql::DataType::String(None)),
             ParamType::Bytes => Self::one_column(name, sql::DataType::Bytea),
+            // The words, big-endian.
+            ParamType::RawSlice => Self::one_column(name, sql::DataType::Bytea),
+            // Vec<u8> as its bytes, other vectors as their length followed by
+            // their ABI encoding, see `ecal_save::scalar_args`.
             ParamType::Vector(_) => Self::one_column(name, sql::DataType::Bytea),
-            ParamType::Enum { .. } => {
+            ParamType::Enum { variants, .. } => {
                 let type_declaration = self.abi.types.get(&type_application.type_id).unwrap();
                 // Option<_>
                 if type_declaration.type_field == "enum Option" {
                     let x = &type_application.type_arguments.as_ref().unwrap()[0];
                     let y = self.abi.types.get(&x.type_id).unwrap();
-                    // Option<Vec<_>>
-                    if y.type_field == "struct Vec" {
-                        let z = &x.type_arguments.as_ref().unwrap()[0];
-                        let z2 = self.abi.types.get(&z.type_id).unwrap();
-                        // Special case for Option<Vec<u8>>
-                        if z2.type_field == "u8" {
-                            Self::one_column(name, sql::DataType::Bytea)
-                        } else {
-                            unimplemented!()
-                        }
-                    } else if y.is_struct() {
-                        Self::one_column(&format!("{name}Id"), sql::DataType::Integer(None))
+                    if let Some(some) = column_option(&self.abi, type_application) {
+                        // The column of `Some`, e.g. Option<Vec<u8>> in a
+                        // bytea column.
+                        let some = TypeApplication {
+                            name: name.clone(),
+                            ..some
+                        };
+                        let some_type = variants.param_types()[1].clone();
+                        self.process_param_type(&some, struct_name, some_type)?
+                    } else if self.referenced_decl(type_application).is_some() {
+                        Self::one_column(&format!("{name}Id"), sql::DataType::BigInt(None))
                     } else {
-                        unimplemented!("{name} {type_application:#?} {type_declaration:#?} {y:#?}")
+                        return Err(format!("`{name}: Option<{}>`", y.type_field));
                     }
+                } else if type_declaration.is_fieldless_enum(&self.abi) {
+                    let enum_name = type_declaration.struct_or_enum_name().unwrap();
+                    let data_type = sql::DataType::Custom(
+                        sql::ObjectName(vec![sql::Ident::new(fieldless_enum_type(&enum_name))]),
+                        vec![],
+                    );
+                    Self::one_column(name, data_type)
                 } else {
-                    // panic!("{type_application:#?}{type_declaration:#?}\n{param_type:#?}")
-                    vec![
-                        Self::column(&format!("\"{name}Variant\""), sql::DataType::Int(None)),
-                        Self::column(&format!("\"{name}Id\""), sql::DataType::BigInt(None)),
-                    ]
+                    // The id of the row in the enum's table, which has the
+                    // variant, see `process_enum`.
+                    Self::one_column(&format!("{name}Id"), sql::DataType::BigInt(None))
                 }
             }
             ParamType::Array(elem_type, _) => {
@@ -256,37 +573,102 @@ impl SQLTableBuilder {
                     .unwrap()
                     .to_owned();
 
+                if decl.is_byte_array(&self.abi) {
+                    return Ok(Self::one_column(name, sql::DataType::Bytea));
+                }
+
                 let inner_type = decl.components.as_ref().unwrap()[0].clone();
                 let inner_decl = self.abi.types.get(&inner_type.type_id).unwrap();
                 if inner_decl.type_field == "enum Option" {
-                    let table_name = sql::ObjectName(vec![sql::Ident::new(format!(
-                        "\"{}\"",
-                        name.as_str().to_capitalized()
-                    ))]);
-
-                    if !self.tables.contains(&table_name.to_string()) {
+                    // The elements are rows of their own, linked to the
+                    // array, so scalars and fieldless enums can't be stored.
+                    let elt = &inner_type.type_arguments.as_ref().unwrap()[0];
+                    let elt_decl = self.referenced_decl(elt).ok_or_else(|| {
+                        format!(
+                            "`{name}: [Option<{}>; _]`, array elements must be structs or enums",
+                            self.abi.types.get(&elt.type_id).unwrap().type_field
+                        )
+                    })?;
+                    // Deleting an element unlinks it from the arrays.
+                    let elt_constraint = sql::TableConstraint::ForeignKey {
+                        name: None,
+                        columns: vec![sql::Ident::new("id")],
+                        foreign_table: sql::ObjectName(vec![sql::quoted_ident(
+                            &elt_decl.struct_or_enum_name().unwrap(),
+                        )]),
+                        referred_columns: vec![sql::Ident::new("id")],
+                        on_delete: Some(sqlparser::ast::ReferentialAction::Cascade),
+                        on_update: None,
+                    };
+                    let struct_name = struct_name.as_ref().unwrap();
+                    // Arrays of the same name share a table, which can only
+                    // hold one type of elements, see below.
+                    let elt_type = inner_type
+                        .type_arguments
+                        .as_ref()
+                        .and_then(|args| args.first())
+                        .and_then(|elt| self.abi.types.get(&elt.type_id))
+                        .map(|elt_decl| elt_decl.type_field.clone());
+                    let table = array_table_name(name.as_str());
+                    match self.array_tables.get(&table) {
+                        Some((other, other_elt_type)) if *other_elt_type != elt_type => {
+                            return Err(format!(
+                                "the table \"{table}\" holds the elements of `{other}.{name}`, which aren't of the same type"
+                            ));
+                        }
+                        Some(_) => {}
+                        None => {
+                            self.array_tables
+                                .insert(table.clone(), (struct_name.clone(), elt_type));
+                        }
+                    }
+                    let table_name = sql::ObjectName(vec![sql::quoted_ident(&table)]);
+                    let parent_column = Self::column(
+                        &array_parent_column(struct_name),
+                        sql::DataType::BigInt(None),
+                    );
+                    let mut foreign_table_ident = sql::Ident::new(struct_name.to_capitalized());
+                    foreign_table_ident.quote_style = Some('"');
+                    let parent_constraint = sql::TableConstraint::ForeignKey {
+                        name: None, // sql::Ident::new("value"),
+                        columns: vec![parent_column.name.clone()],
+                        foreign_table: sql::ObjectName(vec![foreign_table_ident]),
+                        referred_columns: vec![sql::Ident::new("id")],
+                        on_delete: Some(sqlparser::ast::ReferentialAction::Cascade),
+                        on_update: None,
+                    };
+                    // An element is linked once, also when indexers save the
+                    // same value concurrently, see `SaveStmtBuilder::save_value`.
+                    let position_constraint = sql::TableConstraint::Unique {
+                        name: None,
+                        columns: vec![parent_column.name.clone(), sql::Ident::new("position")],
+                        is_primary: false,
+                    };
+
+                    if let Some(builder) = self
+                        .extra_builders
+                        .iter_mut()
+                        .find(|b| b.name == table_name)
+                    {
+                        // Another struct has an array with the same name,
+                        // e.g. `Script.inputs` and `Create.inputs`. They
+                        // share the table, with a column for each parent.
+                        if !builder.columns.iter().any(|c| c.name == parent_column.name) {
+                            builder.columns.push(parent_column);
+                            builder.constraints.push(parent_constraint);
+                            builder.constraints.push(position_constraint);
+                        }
+                    } else {
+                        // The element id, the id of the struct holding the
+                        // array, and the position of the element in it.
                         let columns = vec![
-                            Self::column("id", sql::DataType::Int(None)),
-                            Self::column(
-                                &format!("{}_id", struct_name.as_ref().unwrap().to_snake()),
-                                sql::DataType::Int(None),
-                            ),
+                            Self::column("id", sql::DataType::BigInt(None)),
+                            parent_column,
+                            Self::column("position", sql::DataType::Int(None)),
                         ];
-                        let mut foreign_table_ident =
-                            sql::Ident::new(struct_name.as_ref().unwrap().to_capitalized());
-                        foreign_table_ident.quote_style = Some('"');
-                        let constraints = vec![sql::TableConstraint::ForeignKey {
-                            name: None, // sql::Ident::new("value"),
-                            columns: vec![sql::Ident::new(&format!(
-                                "{}_id",
-                                struct_name.as_ref().unwrap().to_snake()
-                            ))],
-                            foreign_table: sql::ObjectName(vec![foreign_table_ident]),
-                            referred_columns: vec![sql::Ident::new("id")],
-                            on_delete: Some(sqlparser::ast::ReferentialAction::Cascade),
-                            on_update: None,
-                        }];
 
+                        let mut constraints = vec![parent_constraint, position_constraint];
+                        constraints.push(elt_constraint);
                         let builder = sql::CreateTableBuilder::new(table_name.clone())
                             .if_not_exists(true)
                             .columns(columns)
@@ -310,8 +692,9 @@ impl SQLTableBuilder {
                 // Self::one_column(&format!("{name}Id"), sql::DataType::BigInt(None))
                 // panic!("{:?}", r#type)
             }
-            _ => unimplemented!("TODO: `{}: {:?}`", name, param_type),
-        }
+            _ => return Err(format!("`{name}: {param_type:?}`")),
+        };
+        Ok(columns)
     }
 
     // pub enum ParamType {
@@ -323,11 +706,11 @@ impl SQLTableBuilder {
     // x   U256, x
     // x   Bool, x
     // x   B256, x
-    //     Unit,
+    // x   Unit, x
     //     Array(Box<ParamType>, usize),
-    //     Vector(Box<ParamType>),
-    //     StringSlice,
-    //     StringArray(usize),
+    // x   Vector(Box<ParamType>), x
+    // x   StringSlice, x
+    // x   StringArray(usize), x
     //     Struct {
     //         fields: Vec<ParamType>,
     //         generics: Vec<ParamType>,
@@ -336,12 +719,39 @@ impl SQLTableBuilder {
     //         variants: EnumVariants,
     //         generics: Vec<ParamType>,
     //     },
-    //     Tuple(Vec<ParamType>),
-    //     RawSlice,
-    // x   Bytes,
-    // x   String,
+    // x   Tuple(Vec<ParamType>), x
+    // x   RawSlice, x
+    // x   Bytes, x
+    // x   String, x
     // }
 
+    // The struct or enum a `"{name}Id"` column points to, for a field of that
+    // type or an option of it, see `process_param_type`.
+    fn referenced_decl(&self, type_application: &TypeApplication) -> Option<TypeDeclaration> {
+        let mut decl = self.abi.types.get(&type_application.type_id)?;
+        if decl.is_option() {
+            let some = type_application.type_arguments.as_ref()?.first()?;
+            decl = self.abi.types.get(&some.type_id)?;
+        }
+        let has_table = (decl.is_struct() || decl.is_enum())
+            && !decl.is_option()
+            && !decl.is_std_scalar()
+            && !decl.is_fieldless_enum(&self.abi);
+        has_table.then(|| decl.clone())
+    }
+
+    // Rows other rows point to can't be deleted, see `ecal_delete::delete`.
+    fn reference_constraint(column: sql::Ident, table: &str) -> sql::TableConstraint {
+        sql::TableConstraint::ForeignKey {
+            name: None,
+            columns: vec![column],
+            foreign_table: sql::ObjectName(vec![sql::quoted_ident(table)]),
+            referred_columns: vec![sql::Ident::new("id")],
+            on_delete: Some(sqlparser::ast::ReferentialAction::Restrict),
+            on_update: None,
+        }
+    }
+
     fn one_column(name: &str, data_type: sql::DataType) -> Vec<sql::ColumnDef> {
         vec![Self::column(&format!("\"{}\"", name), data_type)]
     }
@@ -355,14 +765,41 @@ impl SQLTableBuilder {
         }
     }
 
-    // id SERIAL PRIMARY KEY
-    fn pk_column() -> sql::ColumnDef {
+    // The primary key, plus the unique content hash. Saving a value inserts
+    // its row unless one with the same content hash exists, see
+    // `SaveStmtBuilder::push_row`.
+    fn key_columns(&self) -> Vec<sql::ColumnDef> {
+        vec![
+            self.pk_column(),
+            sql::ColumnDef {
+                name: sql::Ident::new(CONTENT_HASH_COLUMN),
+                data_type: sql::DataType::Bytea,
+                collation: None,
+                options: vec![
+                    sql::ColumnOptionDef {
+                        name: None,
+                        option: sql::ColumnOption::NotNull,
+                    },
+                    sql::ColumnOptionDef {
+                        name: None,
+                        option: sql::ColumnOption::Unique { is_primary: false },
+                    },
+                ],
+            },
+        ]
+    }
+
+    // id SERIAL PRIMARY KEY, or id BIGINT PRIMARY KEY for content hashes
+    fn pk_column(&self) -> sql::ColumnDef {
+        let data_type = match self.primary_keys {
+            PrimaryKeys::Serial => {
+                sql::DataType::Custom(sqlparser::ast::ObjectName(vec!["SERIAL".into()]), vec![])
+            }
+            PrimaryKeys::ContentHash => sql::DataType::BigInt(None),
+        };
         sql::ColumnDef {
             name: sql::Ident::new("id"),
-            data_type: sql::DataType::Custom(
-                sqlparser::ast::ObjectName(vec!["SERIAL".into()]),
-                vec![],
-            ),
+            data_type,
             collation: None,
             options: vec![sql::ColumnOptionDef {
                 name: None,
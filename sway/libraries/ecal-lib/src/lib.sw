@@ -1,27 +1,28 @@
 library;
 
+mod numbers;
+
+use std::bytes::Bytes;
 use std::string::String;
 
+// Reads up to `len` bytes at offset `seek` from `path`, relative to the
+// host's files root. Paths with `..` or escaping symlinks make the VM panic.
 pub fn read_file_raw(seek: u64, len: u64, path: str) -> raw_slice {
     let path_ptr = path.as_ptr();
     let path_len = path.len();
     let data = (seek, len, path_ptr, path_len);
     let ptr = __addr_of(data);
-    // r_a=0: read_file ecal
-    // r_b: arguments/result pointer
-    asm(r_a: 0, ptr: ptr, r_c: 0, r_d: 0) {
-        ecal r_a ptr r_c r_d;
-    };
-    asm(ptr: ptr) {
-        ptr: raw_slice
+    // r_b: arguments pointer in, (ptr, len) of the file contents out
+    asm(r_a: numbers::READ_FILE, r_b: ptr, r_c: 0, r_d: 0) {
+        ecal r_a r_b r_c r_d;
+        r_b: raw_slice
     }
 }
 
 pub fn println(input: String) {
     let data = (input.as_bytes().buf.ptr(), input.as_bytes().len());
     let ptr = __addr_of(data);
-    // r_a=1: println_str ecal
-    asm(r_a: 1u64, r_b: ptr, r_c: 0u64, r_d: 0u64) {
+    asm(r_a: numbers::PRINTLN, r_b: ptr, r_c: 0u64, r_d: 0u64) {
         ecal r_a r_b r_c r_d;
     }
 }
@@ -31,8 +32,7 @@ pub fn println_str(input: str) {
 }
 
 pub fn println_u64(input: u64) {
-    // r_a=2: println_u64 ecal
-    asm(r_a: 2u64, r_b: input, r_c: 0u64, r_d: 0u64) {
+    asm(r_a: numbers::PRINTLN_U64, r_b: input, r_c: 0u64, r_d: 0u64) {
         ecal r_a r_b r_c r_d;
     }
 }
@@ -41,8 +41,7 @@ pub fn type_id(input: str) -> u64 {
     let input = String::from_ascii_str(input);
     let data = (input.as_bytes().buf.ptr(), input.as_bytes().len());
     let ptr = __addr_of(data);
-    // r_a=7: type_id ecal
-    asm(r_a: 7u64, r_b: ptr, r_c: 0u64, r_d: 0u64) {
+    asm(r_a: numbers::TYPE_ID, r_b: ptr, r_c: 0u64, r_d: 0u64) {
         ecal r_a r_b r_c r_d;
         r_b: u64
     }
@@ -65,36 +64,133 @@ pub fn print_any<T>(t: T) where T: TypeName {
 
     let data = (type_id, __addr_of(t), __size_of_val(t));
     let ptr = __addr_of(data);
-    // r_a=8: print_any ecal
-    asm(r_a: 8u64, r_b: ptr, r_c: 0u64, r_d: 0u64) {
+    asm(r_a: numbers::PRINT_ANY, r_b: ptr, r_c: 0u64, r_d: 0u64) {
         ecal r_a r_b r_c r_d;
     };
 }
 
 pub fn save<T>(t: T) where T: TypeName {
+    let _ = save_value(t, 0);
+}
+
+// Saves `t` and returns the id of its row, e.g. to reference it from other
+// entities. Values already in the database are not saved again, their id is
+// returned instead. With the host's write buffer enabled, this flushes the
+// buffer, so prefer `save` when the id is not needed.
+pub fn save_with_id<T>(t: T) -> u64 where T: TypeName {
+    save_value(t, 1)
+}
+
+fn save_value<T>(t: T, return_id: u64) -> u64 where T: TypeName {
     // For now, logging te value is necessary to ensure it makes it to the ABI
     log(t);
 
     let type_name = T::type_name();
     let type_id = type_id(type_name);
 
-    let data = (type_id, __addr_of(t), __size_of_val(t));
+    let data = (type_id, __addr_of(t), __size_of_val(t), return_id);
+    let ptr = __addr_of(data);
+    // r_b: arguments pointer in, row id out
+    asm(r_a: numbers::SAVE, r_b: ptr, r_c: 0u64, r_d: 0u64) {
+        ecal r_a r_b r_c r_d;
+        r_b: u64
+    }
+}
+
+// Saves all of `values` with as few statements as possible, and returns the
+// ids of their rows, in order. With the host's write buffer enabled, this
+// flushes the buffer first.
+pub fn save_many<T>(values: Vec<T>) -> Vec<u64> where T: TypeName {
+    let mut ids = Vec::new();
+    if values.len() == 0 {
+        return ids;
+    }
+    // For now, logging te value is necessary to ensure it makes it to the ABI
+    log(values.get(0).unwrap());
+
+    let type_name = T::type_name();
+    let type_id = type_id(type_name);
+
+    let data = (type_id, values.ptr(), values.len(), __size_of::<T>());
+    let ptr = __addr_of(data);
+    // r_b: arguments pointer in, address of the ids out
+    let ids_ptr = asm(r_a: numbers::SAVE_MANY, r_b: ptr, r_c: 0u64, r_d: 0u64) {
+        ecal r_a r_b r_c r_d;
+        r_b: raw_ptr
+    };
+    let mut i = 0;
+    while i < values.len() {
+        ids.push(ids_ptr.add::<u64>(i).read::<u64>());
+        i += 1;
+    }
+    ids
+}
+
+// Loads a value of `T` matching `filter`, panics if there is none.
+pub fn load<T>(filter: Filter<T>) -> T where T: TypeName {
+    try_load(filter).unwrap()
+}
+
+// Loads a value of `T` matching `filter`, `None` if there is none. Which one
+// if several match is up to the database.
+pub fn try_load<T>(filter: Filter<T>) -> Option<T> where T: TypeName {
+    let type_name = T::type_name();
+    let type_id = type_id(type_name);
+
+    let data = (type_id, filter.bytes.ptr(), filter.bytes.len());
     let ptr = __addr_of(data);
-    // r_a=3: save ecal
-    asm(r_a: 3u64, r_b: ptr, r_c: 0u64, r_d: 0u64) {
+    // r_b: arguments pointer in, address of the value out
+    asm(r_a: numbers::LOAD, r_b: ptr, r_c: 0u64, r_d: 0u64) {
         ecal r_a r_b r_c r_d;
+        r_b: Option<T>
+    }
+}
+
+// Some of the values matching a filter, and how many match in total.
+pub struct Page<T> {
+    total: u64,
+    values: Vec<T>,
+}
+
+// Loads up to `limit` values of `T` matching `filter`, skipping the first
+// `offset`, in the order of `order_by`. The host returns at most 1000 values
+// at once, `total` tells whether there are more.
+pub fn load_many<T>(filter: Filter<T>, order_by: Order<T>, limit: u64, offset: u64) -> Page<T> where T: TypeName {
+    let type_name = T::type_name();
+    let type_id = type_id(type_name);
+
+    let data = (type_id, filter.bytes.ptr(), filter.bytes.len(), order_by.bytes.ptr(), order_by.bytes.len(), limit, offset);
+    let ptr = __addr_of(data);
+    // r_b: arguments pointer in, address of (total, len, values) out
+    let output = asm(r_a: numbers::LOAD_MANY, r_b: ptr, r_c: 0u64, r_d: 0u64) {
+        ecal r_a r_b r_c r_d;
+        r_b: raw_ptr
     };
+    let total = output.read::<u64>();
+    let len = output.add::<u64>(1).read::<u64>();
+    let first = output.add::<u64>(2);
+    let mut values = Vec::new();
+    let mut i = 0;
+    while i < len {
+        values.push(first.add::<T>(i).read::<T>());
+        i += 1;
+    }
+    Page { total, values }
 }
 
-// TODO: return Option<T>
-pub fn load<T>(_filter: Filter<T>) -> T where T: TypeName {
+// Deletes the rows of `T` matching `filter`, and returns how many were
+// deleted. Elements of array fields go with them, nested structs are kept.
+// Deleting a value nested in another one fails, and the script panics.
+pub fn delete<T>(filter: Filter<T>) -> u64 where T: TypeName {
     let type_name = T::type_name();
     let type_id = type_id(type_name);
 
-    // r_a=4: load ecal
-    asm(r_a: 4u64, r_b: type_id, r_c: 0u64, r_d: 0u64) {
+    let data = (type_id, filter.bytes.ptr(), filter.bytes.len());
+    let ptr = __addr_of(data);
+    // r_b: arguments pointer in, number of deleted rows out
+    asm(r_a: numbers::DELETE, r_b: ptr, r_c: 0u64, r_d: 0u64) {
         ecal r_a r_b r_c r_d;
-        r_b: T
+        r_b: u64
     }
 }
 
@@ -105,18 +201,135 @@ pub struct Field<T, F> {
     phantom: PhantomData<(T, F)>,
 }
 
+// Serialized for the host, see `Filter` in the host's `ecal/filter.rs`.
 pub struct Filter<T> {
+    bytes: Bytes,
     phantom: PhantomData<T>,
 }
 
+const FILTER_ANY: u8 = 0;
+const FILTER_FIELD: u8 = 1;
+const FILTER_AND: u8 = 2;
+const FILTER_OR: u8 = 3;
+
+const OP_EQ: u8 = 0;
+const OP_NE: u8 = 1;
+const OP_LT: u8 = 2;
+const OP_LE: u8 = 3;
+const OP_GT: u8 = 4;
+const OP_GE: u8 = 5;
+
 impl<T> Filter<T> {
     pub fn any() -> Filter<T> {
-        Filter { phantom: PhantomData::<T>{} }
+        let mut bytes = Bytes::new();
+        bytes.push(FILTER_ANY);
+        Filter { bytes, phantom: PhantomData::<T>{} }
+    }
+
+    pub fn and(self, other: Filter<T>) -> Filter<T> {
+        self.combine(FILTER_AND, other)
+    }
+
+    pub fn or(self, other: Filter<T>) -> Filter<T> {
+        self.combine(FILTER_OR, other)
+    }
+
+    fn combine(self, tag: u8, other: Filter<T>) -> Filter<T> {
+        let mut bytes = Bytes::new();
+        bytes.push(tag);
+        bytes.append(self.bytes);
+        bytes.append(other.bytes);
+        Filter { bytes, phantom: PhantomData::<T>{} }
+    }
+}
+
+// Ordering only works on fields stored in one column, e.g. numbers and
+// strings, not on structs, enums, arrays or tuples.
+impl<T, F> Field<T, F> {
+    pub fn eq(self, val: F) -> Filter<T> {
+        self.compare(OP_EQ, val)
+    }
+
+    pub fn ne(self, val: F) -> Filter<T> {
+        self.compare(OP_NE, val)
+    }
+
+    pub fn lt(self, val: F) -> Filter<T> {
+        self.compare(OP_LT, val)
+    }
+
+    pub fn le(self, val: F) -> Filter<T> {
+        self.compare(OP_LE, val)
+    }
+
+    pub fn gt(self, val: F) -> Filter<T> {
+        self.compare(OP_GT, val)
+    }
+
+    pub fn ge(self, val: F) -> Filter<T> {
+        self.compare(OP_GE, val)
+    }
+
+    fn compare(self, op: u8, val: F) -> Filter<T> {
+        // In a tuple, so words like u64 have an address too.
+        let val = (val,);
+        let mut value = Bytes::from(raw_slice::from_parts::<u8>(__addr_of(val), __size_of_val(val)));
+
+        let mut bytes = Bytes::new();
+        bytes.push(FILTER_FIELD);
+        push_u64(bytes, self.field);
+        bytes.push(op);
+        push_u64(bytes, value.len());
+        bytes.append(value);
+        Filter { bytes, phantom: PhantomData::<T>{} }
+    }
+}
+
+// Serialized for the host, see `OrderBy` in the host's `ecal/filter.rs`.
+pub struct Order<T> {
+    bytes: Bytes,
+    phantom: PhantomData<T>,
+}
+
+impl<T> Order<T> {
+    // By id, see `OrderBy`.
+    pub fn none() -> Order<T> {
+        Order { bytes: Bytes::new(), phantom: PhantomData::<T>{} }
+    }
+
+    // Orders the values ordering equal by `self` by `other`.
+    pub fn then(self, other: Order<T>) -> Order<T> {
+        let mut bytes = self.bytes;
+        bytes.append(other.bytes);
+        Order { bytes, phantom: PhantomData::<T>{} }
     }
 }
 
 impl<T, F> Field<T, F> {
-    pub fn eq(self, _val: F) -> Filter<T> {
-        Filter { phantom: PhantomData::<T>{} }
+    pub fn asc(self) -> Order<T> {
+        self.order(0)
+    }
+
+    pub fn desc(self) -> Order<T> {
+        self.order(1)
+    }
+
+    fn order(self, desc: u8) -> Order<T> {
+        let mut bytes = Bytes::new();
+        push_u64(bytes, self.field);
+        bytes.push(desc);
+        Order { bytes, phantom: PhantomData::<T>{} }
+    }
+}
+
+// Big-endian, as the host reads it.
+fn push_u64(ref mut bytes: Bytes, x: u64) {
+    let mut i = 8;
+    while i > 0 {
+        i -= 1;
+        let byte = asm(r1: (x >> (i * 8)) & 0xff) {
+            r1: u8
+        };
+        bytes.push(byte);
     }
 }
\ No newline at end of file
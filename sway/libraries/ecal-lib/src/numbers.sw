@@ -0,0 +1,33 @@
+library;
+
+// Generated by `cargo run -- ecal-numbers`. Do not edit by hand.
+
+// rB = &(seek, len, path_ptr, path_len)
+pub const READ_FILE: u64 = 0;
+
+// rB = &(str_ptr, str_len)
+pub const PRINTLN: u64 = 1;
+
+// rB = value
+pub const PRINTLN_U64: u64 = 2;
+
+// rB = &(type_id, addr, size, return_id)
+pub const SAVE: u64 = 3;
+
+// rB = &(type_id, filter_ptr, filter_len)
+pub const LOAD: u64 = 4;
+
+// rB = &(type_id, filter_ptr, filter_len)
+pub const DELETE: u64 = 5;
+
+// rB = &(type_id, addr, count, elt_size)
+pub const SAVE_MANY: u64 = 6;
+
+// rB = &(type_name_ptr, type_name_len)
+pub const TYPE_ID: u64 = 7;
+
+// rB = &(type_id, addr, size)
+pub const PRINT_ANY: u64 = 8;
+
+// rB = &(type_id, filter_ptr, filter_len, order_ptr, order_len, limit, offset)
+pub const LOAD_MANY: u64 = 9;
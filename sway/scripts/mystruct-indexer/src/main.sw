@@ -1,6 +1,7 @@
 script;
 
 use std::string::String;
+use std::u128::U128;
 
 use my_contract_lib::*;
 use ecal_lib::{TypeName, Filter};
@@ -65,6 +66,95 @@ impl TypeName for V {
     }
 } 
 
+// A Postgres ENUM in the database.
+enum Color {
+    Red: (),
+    Green: (),
+}
+
+fn is_green(color: Color) -> bool {
+    match color {
+        Color::Green => true,
+        _ => false,
+    }
+}
+
+// A field of every scalar type, to check they round-trip through the
+// database.
+struct Scalars {
+    a: u8,
+    b: u16,
+    c: u32,
+    d: u64,
+    e: u256,
+    f: b256,
+    g: bool,
+    h: str[5],
+    i: (u64, bool),
+    j: (),
+    k: Color,
+    l: U128,
+}
+
+impl TypeName for Scalars {
+    fn type_name() -> str {
+        "struct Scalars"
+    }
+}
+
+// A row of the Shape table, with a variant column and a column per variant
+// holding a value.
+enum Shape {
+    Circle: u64,
+    Rect: (u64, u64),
+    Empty: (),
+}
+
+fn radius(shape: Shape) -> u64 {
+    match shape {
+        Shape::Circle(r) => r,
+        _ => 0,
+    }
+}
+
+fn area(shape: Shape) -> u64 {
+    match shape {
+        Shape::Rect((w, h)) => w * h,
+        _ => 0,
+    }
+}
+
+struct Drawing {
+    circle: Shape,
+    rect: Shape,
+    empty: Shape,
+}
+
+impl TypeName for Drawing {
+    fn type_name() -> str {
+        "struct Drawing"
+    }
+}
+
+// Nested values loaded by id: a struct in an enum variant, an option, and
+// the elements of an array.
+enum Event {
+    Transfer: MyOtherStruct,
+    Burn: u64,
+}
+
+struct Log {
+    event: Event,
+    maybe: Option<MyOtherStruct>,
+    items: [Option<MyOtherStruct>; 3],
+}
+
+impl TypeName for Log {
+    fn type_name() -> str {
+        "struct Log"
+    }
+}
+
 fn main(mystruct: MyStruct) {
     let myotherstruct = MyOtherStruct {
         value: 34
@@ -90,8 +180,7 @@ fn main(mystruct: MyStruct) {
     ecal_lib::save(mycomplexstruct);
 
     let x: MyComplexStruct = ecal_lib::load(
-        // the filter argument does not do anything yet
-        MyComplexStruct::one().eq(mystruct)
+        MyComplexStruct::one().eq(mystruct).and(MyComplexStruct::three().ge(mycomplexstruct.three))
     );
 
     ecal_lib::print_any(x);
@@ -122,6 +211,11 @@ fn main(mystruct: MyStruct) {
     
     ecal_lib::save(r);
 
+    // Saving a value again returns the id of the existing row.
+    let r_id = ecal_lib::save_with_id(r);
+    assert(r_id != 0);
+    assert_eq(r_id, ecal_lib::save_with_id(r));
+
     let r2: R = ecal_lib::load(ecal_lib::Filter::<R>::any());
     ecal_lib::print_any(r2);
 
@@ -132,4 +226,84 @@ fn main(mystruct: MyStruct) {
     };
 
     ecal_lib::save(v);
+
+    let scalars = Scalars {
+        a: 255,
+        b: 65535,
+        c: 4294967295,
+        d: 18446744073709551615,
+        e: 0x0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f20u256,
+        f: 0x0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f20,
+        g: true,
+        h: __to_str_array("hello"),
+        i: (42, true),
+        j: (),
+        k: Color::Green,
+        l: U128::from((1, 2)),
+    };
+    ecal_lib::save(scalars);
+    let scalars_2: Scalars = ecal_lib::load(ecal_lib::Filter::<Scalars>::any());
+    assert_eq(scalars.a, scalars_2.a);
+    assert_eq(scalars.b, scalars_2.b);
+    assert_eq(scalars.c, scalars_2.c);
+    assert_eq(scalars.d, scalars_2.d);
+    assert_eq(scalars.e, scalars_2.e);
+    assert_eq(scalars.f, scalars_2.f);
+    assert_eq(scalars.g, scalars_2.g);
+    assert(from_str_array(scalars_2.h) == "hello");
+    assert_eq(scalars.i.0, scalars_2.i.0);
+    assert_eq(scalars.i.1, scalars_2.i.1);
+    assert(is_green(scalars_2.k));
+    assert_eq(scalars.l, scalars_2.l);
+
+    let drawing = Drawing {
+        circle: Shape::Circle(7),
+        rect: Shape::Rect((3, 4)),
+        empty: Shape::Empty,
+    };
+    ecal_lib::save(drawing);
+    let drawing_2: Drawing = ecal_lib::load(ecal_lib::Filter::<Drawing>::any());
+    assert_eq(radius(drawing_2.circle), 7);
+    assert_eq(area(drawing_2.rect), 12);
+    assert_eq(radius(drawing_2.empty) + area(drawing_2.empty), 0);
+
+    let log = Log {
+        event: Event::Transfer(MyOtherStruct { value: 2001 }),
+        maybe: Option::Some(MyOtherStruct { value: 2002 }),
+        items: [Option::None, Option::Some(MyOtherStruct { value: 2003 }), Option::None],
+    };
+    ecal_lib::save(log);
+    let log_2: Log = ecal_lib::load(ecal_lib::Filter::<Log>::any());
+    match log_2.event {
+        Event::Transfer(other) => assert_eq(other.value, 2001),
+        _ => revert(0),
+    }
+    assert_eq(log_2.maybe.unwrap().value, 2002);
+    assert(log_2.items[0].is_none());
+    assert_eq(log_2.items[1].unwrap().value, 2003);
+    assert(log_2.items[2].is_none());
+
+    let mut others = Vec::new();
+    others.push(MyOtherStruct { value: 1001 });
+    others.push(MyOtherStruct { value: 1002 });
+    others.push(MyOtherStruct { value: 1001 });
+    let ids = ecal_lib::save_many(others);
+    assert_eq(ids.len(), 3);
+    assert(ids.get(0).unwrap() != ids.get(1).unwrap());
+    // Equal values share a row.
+    assert_eq(ids.get(0).unwrap(), ids.get(2).unwrap());
+
+    let page = ecal_lib::load_many(
+        MyComplexStruct::three().eq(99),
+        MyComplexStruct::three().desc(),
+        10,
+        0,
+    );
+    assert(page.total > 0);
+    assert_eq(page.values.get(0).unwrap().three, 99);
+
+    // Removes `mycomplexstruct`, saved above.
+    let deleted = ecal_lib::delete(MyComplexStruct::three().eq(99));
+    assert(deleted > 0);
+    assert(ecal_lib::try_load(MyComplexStruct::three().eq(99)).is_none());
 }
\ No newline at end of file